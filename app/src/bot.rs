@@ -1,4 +1,5 @@
 pub mod event;
 pub mod event_loop;
+pub mod router;
 pub mod traq_api;
 pub mod traq_server_connecter;
@@ -1,96 +1,427 @@
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
 
 pub mod fields;
 pub use fields::*;
 
+/// A frame of the bot websocket, `{"type": ..., "reqId": ..., "body": ...}`.
+#[derive(Clone)]
+pub struct Event {
+    pub req_id: String,
+    pub body: EventBody,
+}
+
+/// A frame with the body not decoded yet.
 #[derive(Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub enum Event {
-    System { req_id: String, body: System },
-    Message { req_id: String, body: Message },
-    // todo: not implemented yet
-    // Channel,
-    // User,
-    // UserGroup,
-    // Stamp,
-    // Tag,
+struct RawEvent {
+    r#type: String,
+    #[serde(rename = "reqId")]
+    req_id: String,
+    body: Value,
 }
 
-impl Event {
-    pub fn from_json(json: &str) -> Result<Event, serde_json::Error> {
-        let cache: serde_json::Value = serde_json::from_str(json).unwrap();
-
-        let r#type = cache.get("type").unwrap().as_str().unwrap();
-        let req_id = cache.get("reqId").unwrap().as_str().unwrap();
-        let body = cache.get("body").unwrap().clone();
-
-        match r#type {
-            // System
-            "PING" => Ok(Event::System {
-                req_id: req_id.to_string(),
-                body: System::Ping {
-                    event_time: serde_json::from_value(body).unwrap(),
-                },
-            }),
-            "JOINED" => Ok(Event::System {
-                req_id: req_id.to_string(),
-                body: System::Joined(serde_json::from_value(body).unwrap()),
-            }),
-            "LEFT" => Ok(Event::System {
-                req_id: req_id.to_string(),
-                body: System::Left(serde_json::from_value(body).unwrap()),
-            }),
-            // Message
-            "MESSAGE_CREATED" => Ok(Event::Message {
-                req_id: req_id.to_string(),
-                body: Message::MessageCreated(serde_json::from_value(body).unwrap()),
-            }),
-            "MESSAGE_DELETED" => Ok(Event::Message {
-                req_id: req_id.to_string(),
-                body: Message::MessageDeleted(serde_json::from_value(body).unwrap()),
-            }),
-            "MESSAGE_UPDATED" => Ok(Event::Message {
-                req_id: req_id.to_string(),
-                body: Message::MessageUpdated(serde_json::from_value(body).unwrap()),
-            }),
-            "DIRECT_MESSAGE_CREATED" => Ok(Event::Message {
-                req_id: req_id.to_string(),
-                body: Message::DirectMessageCreated(serde_json::from_value(body).unwrap()),
-            }),
-            "DIRECT_MESSAGE_DELETED" => Ok(Event::Message {
-                req_id: req_id.to_string(),
-                body: Message::DirectMessageDeleted(serde_json::from_value(body).unwrap()),
-            }),
-            "DIRECT_MESSAGE_UPDATED" => Ok(Event::Message {
-                req_id: req_id.to_string(),
-                body: Message::DirectMessageUpdated(serde_json::from_value(body).unwrap()),
-            }),
-            "BOT_MESSAGE_STAMPS_UPDATED" => Ok(Event::Message {
-                req_id: req_id.to_string(),
-                body: Message::BotMessageStampsUpdated(serde_json::from_value(body).unwrap()),
-            }),
+/// Defines `EventBody` from `for_each_event`.
+macro_rules! event_bodies {
+    ($($variant:ident($payload:ty) => $kind:literal $method:ident,)*) => {
+        /// The event, tagged by `type` on the wire with its payload in `body`.
+        #[derive(Clone)]
+        pub enum EventBody {
+            $($variant($payload),)*
+            /// An event type this crate does not know yet. Kept as received,
+            /// so it serializes back to the same frame.
+            Unknown { r#type: String, body: Value },
+        }
+
+        impl EventBody {
+            /// The `type` of the event on the wire, e.g. `MESSAGE_CREATED`.
+            pub fn kind(&self) -> &str {
+                match self {
+                    $(EventBody::$variant(_) => $kind,)*
+                    EventBody::Unknown { r#type, .. } => r#type,
+                }
+            }
+
+            /// Decode the body of an event of the type. Types this crate does
+            /// not know are kept as `Unknown`.
+            pub fn from_parts(r#type: String, body: Value) -> Result<Self, EventParseError> {
+                match r#type.as_str() {
+                    $($kind => match <$payload>::deserialize(&body) {
+                        Ok(payload) => Ok(EventBody::$variant(payload)),
+                        Err(source) => Err(EventParseError::InvalidBody {
+                            r#type,
+                            raw: body,
+                            source,
+                        }),
+                    },)*
+                    _ => Ok(EventBody::Unknown { r#type, body }),
+                }
+            }
+
+            /// The body as sent on the wire.
+            pub fn to_value(&self) -> Result<Value, serde_json::Error> {
+                match self {
+                    $(EventBody::$variant(payload) => serde_json::to_value(payload),)*
+                    EventBody::Unknown { body, .. } => Ok(body.clone()),
+                }
+            }
+        }
+    };
+}
+
+/// Calls `$callback!` with every event type this crate knows: the variant of
+/// `EventBody`, its payload, the `type` on the wire and the handler method of
+/// `Router`. The one place these are written.
+macro_rules! for_each_event {
+    ($callback:ident) => {
+        $callback! {
+            // system
+            Ping($crate::event::Ping) => "PING" on_ping,
+            Joined($crate::event::JoinedLeft) => "JOINED" on_joined,
+            Left($crate::event::JoinedLeft) => "LEFT" on_left,
+            // message
+            MessageCreated($crate::event::MessageCreatedUpdated) => "MESSAGE_CREATED" on_message_created,
+            MessageDeleted($crate::event::MessageDeleted) => "MESSAGE_DELETED" on_message_deleted,
+            MessageUpdated($crate::event::MessageCreatedUpdated) => "MESSAGE_UPDATED" on_message_updated,
+            DirectMessageCreated($crate::event::MessageCreatedUpdated) => "DIRECT_MESSAGE_CREATED" on_direct_message_created,
+            DirectMessageDeleted($crate::event::MessageDeleted) => "DIRECT_MESSAGE_DELETED" on_direct_message_deleted,
+            DirectMessageUpdated($crate::event::MessageCreatedUpdated) => "DIRECT_MESSAGE_UPDATED" on_direct_message_updated,
+            BotMessageStampsUpdated($crate::event::BotMessageStampsUpdated) => "BOT_MESSAGE_STAMPS_UPDATED" on_stamps_updated,
             // channel
-            "CHANNEL_CREATED" => todo!(),
-            "CHANNEL_TOPIC_CHANGED" => todo!(),
+            ChannelCreated($crate::event::ChannelCreated) => "CHANNEL_CREATED" on_channel_created,
+            ChannelTopicChanged($crate::event::ChannelTopicChanged) => "CHANNEL_TOPIC_CHANGED" on_channel_topic_changed,
             // user
-            "USER_CREATED" => todo!(),
-            "USER_ACTIVATED" => todo!(),
+            UserCreated($crate::event::UserCreatedActivated) => "USER_CREATED" on_user_created,
+            UserActivated($crate::event::UserCreatedActivated) => "USER_ACTIVATED" on_user_activated,
             // user group
-            "USER_GROUP_CREATED" => todo!(),
-            "USER_GROUP_UPDATED" => todo!(),
-            "USER_GROUP_DELETED" => todo!(),
-            "USER_GROUP_MEMBER_ADDED" => todo!(),
-            "USER_GROUP_MEMBER_UPDATED" => todo!(),
-            "USER_GROUP_MEMBER_REMOVED" => todo!(),
-            "USER_GROUP_ADMIN_ADDED" => todo!(),
-            "USER_GROUP_ADMIN_REMOVED" => todo!(),
+            UserGroupCreated($crate::event::UserGroupCreated) => "USER_GROUP_CREATED" on_user_group_created,
+            UserGroupUpdated($crate::event::UserGroupUpdatedDeleted) => "USER_GROUP_UPDATED" on_user_group_updated,
+            UserGroupDeleted($crate::event::UserGroupUpdatedDeleted) => "USER_GROUP_DELETED" on_user_group_deleted,
+            UserGroupMemberAdded($crate::event::UserGroupMemberEvent) => "USER_GROUP_MEMBER_ADDED" on_user_group_member_added,
+            UserGroupMemberUpdated($crate::event::UserGroupMemberEvent) => "USER_GROUP_MEMBER_UPDATED" on_user_group_member_updated,
+            UserGroupMemberRemoved($crate::event::UserGroupMemberEvent) => "USER_GROUP_MEMBER_REMOVED" on_user_group_member_removed,
+            UserGroupAdminAdded($crate::event::UserGroupMemberEvent) => "USER_GROUP_ADMIN_ADDED" on_user_group_admin_added,
+            UserGroupAdminRemoved($crate::event::UserGroupMemberEvent) => "USER_GROUP_ADMIN_REMOVED" on_user_group_admin_removed,
             // stamp
-            "STAMP_CREATED" => todo!(),
+            StampCreated($crate::event::StampCreated) => "STAMP_CREATED" on_stamp_created,
             // tag
-            "TAG_ADDED" => todo!(),
-            "TAG_REMOVED" => todo!(),
-            // invalid
-            _ => panic!("Invalid event type: {}", r#type),
+            TagAdded($crate::event::TagAddedRemoved) => "TAG_ADDED" on_tag_added,
+            TagRemoved($crate::event::TagAddedRemoved) => "TAG_REMOVED" on_tag_removed,
+        }
+    };
+}
+pub(crate) use for_each_event;
+
+for_each_event!(event_bodies);
+
+#[derive(Debug)]
+pub enum EventParseError {
+    /// The frame is not JSON.
+    Json(serde_json::Error),
+    /// `type`, `reqId` or `body` is missing or not of the expected type.
+    MissingField(&'static str),
+    /// The body does not match the event type.
+    InvalidBody {
+        r#type: String,
+        raw: Value,
+        source: serde_json::Error,
+    },
+}
+
+impl std::fmt::Display for EventParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            EventParseError::Json(e) => write!(f, "invalid json: {}", e),
+            EventParseError::MissingField(field) => write!(f, "missing field: {}", field),
+            EventParseError::InvalidBody { r#type, source, .. } => {
+                write!(f, "invalid body of {}: {}", r#type, source)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EventParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EventParseError::Json(e) => Some(e),
+            EventParseError::MissingField(_) => None,
+            EventParseError::InvalidBody { source, .. } => Some(source),
+        }
+    }
+}
+
+/// Take the string field out of the frame.
+fn take_str(frame: &mut Value, field: &'static str) -> Result<String, EventParseError> {
+    match frame.get_mut(field).map(Value::take) {
+        Some(Value::String(value)) => Ok(value),
+        _ => Err(EventParseError::MissingField(field)),
+    }
+}
+
+impl Event {
+    /// Parse a frame of the bot websocket. Unknown event types are returned as
+    /// `EventBody::Unknown` rather than an error.
+    pub fn from_json(json: &str) -> Result<Event, EventParseError> {
+        let mut frame: Value = serde_json::from_str(json).map_err(EventParseError::Json)?;
+
+        let r#type = take_str(&mut frame, "type")?;
+        let req_id = take_str(&mut frame, "reqId")?;
+        let body = frame
+            .get_mut("body")
+            .map(Value::take)
+            .ok_or(EventParseError::MissingField("body"))?;
+
+        Ok(Event {
+            req_id,
+            body: EventBody::from_parts(r#type, body)?,
+        })
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(self)
+    }
+}
+
+impl Serialize for Event {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        RawEvent {
+            r#type: self.body.kind().to_string(),
+            req_id: self.req_id.clone(),
+            body: self.body.to_value().map_err(serde::ser::Error::custom)?,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Event {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let RawEvent {
+            r#type,
+            req_id,
+            body,
+        } = RawEvent::deserialize(deserializer)?;
+
+        Ok(Event {
+            req_id,
+            body: EventBody::from_parts(r#type, body).map_err(de::Error::custom)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn user() -> Value {
+        json!({
+            "id": "u1",
+            "name": "alice",
+            "displayName": "Alice",
+            "iconId": "i1",
+            "bot": false,
+        })
+    }
+
+    fn channel() -> Value {
+        json!({
+            "id": "c1",
+            "name": "general",
+            "path": "#general",
+            "parentId": "c0",
+            "creator": user(),
+            "createdAt": "2024-01-01T00:00:00Z",
+            "updatedAt": "2024-01-01T00:00:00Z",
+        })
+    }
+
+    fn message() -> Value {
+        json!({
+            "eventTime": "2024-01-01T00:00:00Z",
+            "message": {
+                "id": "m1",
+                "user": user(),
+                "channelId": "c1",
+                "text": "!{\"type\":\"user\",\"raw\":\"@bot\",\"id\":\"b1\"} -ping",
+                "plainText": "@bot -ping",
+                "embedded": [{ "raw": "@bot", "type": "user", "id": "b1" }],
+                "createdAt": "2024-01-01T00:00:00Z",
+                "updatedAt": "2024-01-01T00:00:00Z",
+            },
+        })
+    }
+
+    fn group_member(role: Option<&str>) -> Value {
+        let mut member = json!({ "groupId": "g1", "userId": "u1" });
+        if let Some(role) = role {
+            member["role"] = json!(role);
+        }
+        json!({ "eventTime": "2024-01-01T00:00:00Z", "groupMember": member })
+    }
+
+    /// A body of every known event type.
+    fn bodies() -> Vec<(&'static str, Value)> {
+        let time = "2024-01-01T00:00:00Z";
+
+        vec![
+            ("PING", json!({ "eventTime": time })),
+            ("JOINED", json!({ "eventTime": time, "channel": channel() })),
+            ("LEFT", json!({ "eventTime": time, "channel": channel() })),
+            ("MESSAGE_CREATED", message()),
+            (
+                "MESSAGE_DELETED",
+                json!({ "eventTime": time, "message": { "id": "m1", "channelId": "c1" } }),
+            ),
+            ("MESSAGE_UPDATED", message()),
+            ("DIRECT_MESSAGE_CREATED", message()),
+            (
+                "DIRECT_MESSAGE_DELETED",
+                json!({
+                    "eventTime": time,
+                    "message": { "id": "m1", "userId": "u1", "channelId": "c1" },
+                }),
+            ),
+            ("DIRECT_MESSAGE_UPDATED", message()),
+            (
+                "BOT_MESSAGE_STAMPS_UPDATED",
+                json!({
+                    "eventTime": time,
+                    "messageId": "m1",
+                    "stamps": [{
+                        "stampId": "s1",
+                        "userId": "u1",
+                        "stampName": "repeat",
+                        "count": 2,
+                        "createdAt": time,
+                        "updatedAt": time,
+                    }],
+                }),
+            ),
+            (
+                "CHANNEL_CREATED",
+                json!({ "eventTime": time, "channel": channel() }),
+            ),
+            (
+                "CHANNEL_TOPIC_CHANGED",
+                json!({
+                    "eventTime": time,
+                    "channel": channel(),
+                    "topic": "hello",
+                    "updater": user(),
+                }),
+            ),
+            ("USER_CREATED", json!({ "eventTime": time, "user": user() })),
+            (
+                "USER_ACTIVATED",
+                json!({ "eventTime": time, "user": user() }),
+            ),
+            (
+                "USER_GROUP_CREATED",
+                json!({
+                    "eventTime": time,
+                    "group": {
+                        "id": "g1",
+                        "name": "team",
+                        "description": "",
+                        "type": "grade",
+                        "icon": "i1",
+                        "admins": [{ "groupId": "g1", "userId": "u1" }],
+                        "members": [{ "groupId": "g1", "userId": "u1", "role": "lead" }],
+                        "createdAt": time,
+                        "updatedAt": time,
+                    },
+                }),
+            ),
+            (
+                "USER_GROUP_UPDATED",
+                json!({ "eventTime": time, "groupId": "g1" }),
+            ),
+            (
+                "USER_GROUP_DELETED",
+                json!({ "eventTime": time, "groupId": "g1" }),
+            ),
+            ("USER_GROUP_MEMBER_ADDED", group_member(Some("lead"))),
+            ("USER_GROUP_MEMBER_UPDATED", group_member(Some("lead"))),
+            ("USER_GROUP_MEMBER_REMOVED", group_member(None)),
+            ("USER_GROUP_ADMIN_ADDED", group_member(None)),
+            ("USER_GROUP_ADMIN_REMOVED", group_member(None)),
+            (
+                "STAMP_CREATED",
+                json!({
+                    "eventTime": time,
+                    "id": "s1",
+                    "name": "repeat",
+                    "fileId": "f1",
+                    "creator": user(),
+                }),
+            ),
+            (
+                "TAG_ADDED",
+                json!({ "eventTime": time, "tagId": "t1", "tag": "rust" }),
+            ),
+            (
+                "TAG_REMOVED",
+                json!({ "eventTime": time, "tagId": "t1", "tag": "rust" }),
+            ),
+        ]
+    }
+
+    fn frame(r#type: &str, body: Value) -> Value {
+        json!({ "type": r#type, "reqId": "r1", "body": body })
+    }
+
+    #[test]
+    fn known_events_round_trip() {
+        for (r#type, body) in bodies() {
+            let frame = frame(r#type, body);
+
+            let event = Event::from_json(&frame.to_string())
+                .unwrap_or_else(|e| panic!("{}: {}", r#type, e));
+            assert_eq!(event.req_id, "r1");
+            assert_eq!(event.body.kind(), r#type);
+            assert!(
+                !matches!(event.body, EventBody::Unknown { .. }),
+                "{} parsed as unknown",
+                r#type
+            );
+
+            let json: Value = serde_json::from_str(&event.to_json().unwrap()).unwrap();
+            assert_eq!(json, frame, "{} does not round-trip", r#type);
+        }
+    }
+
+    #[test]
+    fn unknown_event_round_trips() {
+        let frame = frame("NEW_EVENT", json!({ "eventTime": "t", "x": [1, 2] }));
+
+        let event = Event::from_json(&frame.to_string()).unwrap();
+        assert!(matches!(&event.body, EventBody::Unknown { r#type, .. } if r#type == "NEW_EVENT"));
+
+        let json: Value = serde_json::from_str(&event.to_json().unwrap()).unwrap();
+        assert_eq!(json, frame);
+    }
+
+    #[test]
+    fn malformed_body_keeps_raw() {
+        let body = json!({ "eventTime": "t" });
+        let frame = frame("MESSAGE_CREATED", body.clone());
+
+        match Event::from_json(&frame.to_string()) {
+            Err(EventParseError::InvalidBody { r#type, raw, .. }) => {
+                assert_eq!(r#type, "MESSAGE_CREATED");
+                assert_eq!(raw, body);
+            }
+            _ => panic!("expected InvalidBody"),
         }
     }
+
+    #[test]
+    fn missing_type() {
+        let frame = json!({ "reqId": "r1", "body": {} });
+
+        assert!(matches!(
+            Event::from_json(&frame.to_string()),
+            Err(EventParseError::MissingField("type"))
+        ));
+    }
 }
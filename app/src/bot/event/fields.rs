@@ -1,33 +1,31 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub enum System {
-    Ping { event_time: String },
-    Joined(JoinedLeft),
-    Left(JoinedLeft),
+pub struct Ping {
+    pub event_time: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct JoinedLeft {
     pub event_time: String,
     pub channel: Channel,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Channel {
     pub id: String,
     pub name: String,
     pub path: String,
     pub parent_id: String,
-    pub creator: (), // User
+    pub creator: User,
     pub created_at: String,
     pub updated_at: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct User {
     pub id: String,
@@ -37,26 +35,14 @@ pub struct User {
     pub bot: bool,
 }
 
-#[derive(Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub enum Message {
-    MessageCreated(MessageCreatedUpdated),
-    MessageDeleted(MessageDeleted),
-    MessageUpdated(MessageCreatedUpdated),
-    DirectMessageCreated(MessageCreatedUpdated),
-    DirectMessageDeleted(MessageDeleted),
-    DirectMessageUpdated(MessageCreatedUpdated),
-    BotMessageStampsUpdated(BotMessageStampsUpdated),
-}
-
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct MessageCreatedUpdated {
     pub event_time: String,
     pub message: MessageBody,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct MessageBody {
     pub id: String,
@@ -69,7 +55,7 @@ pub struct MessageBody {
     pub updated_at: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Embedded {
     pub raw: String,
@@ -77,23 +63,23 @@ pub struct Embedded {
     pub id: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct MessageDeleted {
     pub event_time: String,
     pub message: MessageDeletedBody,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct MessageDeletedBody {
     pub id: String,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub user_id: String,
     pub channel_id: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct BotMessageStampsUpdated {
     pub event_time: String,
@@ -101,12 +87,107 @@ pub struct BotMessageStampsUpdated {
     pub stamps: Vec<Stamp>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Stamp {
     pub stamp_id: String,
     pub user_id: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub stamp_name: String,
     pub count: u32,
     pub created_at: String,
     pub updated_at: String,
 }
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChannelCreated {
+    pub event_time: String,
+    pub channel: Channel,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChannelTopicChanged {
+    pub event_time: String,
+    pub channel: Channel,
+    pub topic: String,
+    pub updater: User,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserCreatedActivated {
+    pub event_time: String,
+    pub user: User,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserGroupCreated {
+    pub event_time: String,
+    pub group: UserGroup,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserGroup {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub r#type: String,
+    pub icon: String,
+    pub admins: Vec<UserGroupAdmin>,
+    pub members: Vec<UserGroupMember>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserGroupAdmin {
+    pub group_id: String,
+    pub user_id: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserGroupMember {
+    pub group_id: String,
+    pub user_id: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub role: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserGroupUpdatedDeleted {
+    pub event_time: String,
+    pub group_id: String,
+}
+
+/// Member and admin events. Admin events carry no role.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserGroupMemberEvent {
+    pub event_time: String,
+    pub group_member: UserGroupMember,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StampCreated {
+    pub event_time: String,
+    pub id: String,
+    pub name: String,
+    pub file_id: String,
+    pub creator: User,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TagAddedRemoved {
+    pub event_time: String,
+    pub tag_id: String,
+    pub tag: String,
+}
@@ -1,41 +1,214 @@
+use std::{collections::VecDeque, sync::Arc};
+
 use futures::{SinkExt, StreamExt};
-use tokio_tungstenite::tungstenite::Message;
+use tokio::{
+    sync::{watch, Semaphore},
+    task::JoinSet,
+};
+use tokio_tungstenite::tungstenite::{
+    protocol::{frame::coding::CloseCode, CloseFrame},
+    Error, Message,
+};
 
 use crate::{
+    router::Router,
     traq_api::TraqApi,
-    traq_server_connecter::{TraqServerConnecter, TraqServerConnecterBuilder},
+    traq_server_connecter::{ReconnectPolicy, TraqServerConnecter, TraqServerConnecterBuilder},
 };
 
+/// Events `EventLoop::run_concurrent` keeps while every handler slot is taken.
+/// More are dropped, so a flood of events cannot grow memory without bound.
+pub const MAX_PENDING_EVENTS: usize = 1024;
+
 pub struct EventLoopBuilder {
     pub connecter: TraqServerConnecterBuilder,
+    pub reconnect: ReconnectPolicy,
 }
 
 impl EventLoopBuilder {
-    pub async fn build(self) -> EventLoop {
-        EventLoop {
-            connecter: self.connecter.build().await,
-        }
+    pub async fn build(self) -> Result<EventLoop, Error> {
+        Ok(EventLoop {
+            connecter: self.connecter.build_with_retry(&self.reconnect).await?,
+            reconnect: self.reconnect,
+            shutdown: Arc::new(watch::Sender::new(false)),
+        })
     }
 }
 
 pub struct EventLoop {
     connecter: TraqServerConnecter,
+    reconnect: ReconnectPolicy,
+    shutdown: Arc<watch::Sender<bool>>,
+}
+
+/// Stops `EventLoop::run_concurrent` from another task.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    shutdown: Arc<watch::Sender<bool>>,
+}
+
+impl ShutdownHandle {
+    /// Stop reading events. Handlers already running are awaited.
+    pub fn shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+}
+
+/// A Close frame sent by the server.
+#[derive(Debug, Clone)]
+pub struct CloseEvent {
+    pub code: CloseCode,
+    pub reason: String,
+}
+
+impl From<Option<CloseFrame>> for CloseEvent {
+    fn from(frame: Option<CloseFrame>) -> Self {
+        match frame {
+            Some(frame) => Self {
+                code: frame.code,
+                reason: frame.reason.to_string(),
+            },
+            None => Self {
+                code: CloseCode::Status,
+                reason: String::new(),
+            },
+        }
+    }
+}
+
+impl CloseEvent {
+    /// Whether connecting again may help. The server rejecting what the bot
+    /// sends will not change by reconnecting.
+    pub fn is_recoverable(&self) -> bool {
+        !matches!(
+            self.code,
+            CloseCode::Policy | CloseCode::Unsupported | CloseCode::Invalid
+        )
+    }
+}
+
+impl std::fmt::Display for CloseEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} ({})", u16::from(self.code), self.code)?;
+        if !self.reason.is_empty() {
+            write!(f, ": {}", self.reason)?;
+        }
+        Ok(())
+    }
+}
+
+/// Why the event loop stopped.
+#[derive(Debug)]
+pub enum EventLoopError {
+    /// The connection was lost and the reconnect policy gave up.
+    Connection(Error),
+    /// The server closed the connection for a reason reconnecting does not fix.
+    Closed(CloseEvent),
+}
+
+impl From<Error> for EventLoopError {
+    fn from(e: Error) -> Self {
+        Self::Connection(e)
+    }
+}
+
+impl std::fmt::Display for EventLoopError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            EventLoopError::Connection(e) => write!(f, "connection lost: {}", e),
+            EventLoopError::Closed(close) => write!(f, "closed by server: {}", close),
+        }
+    }
+}
+
+impl std::error::Error for EventLoopError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EventLoopError::Connection(e) => Some(e),
+            EventLoopError::Closed(_) => None,
+        }
+    }
 }
 
 impl EventLoop {
     pub async fn build_from_host_and_token(
         host: impl Into<String>,
         token: impl Into<String>,
-    ) -> EventLoop {
+    ) -> Result<EventLoop, Error> {
         let connecter = TraqServerConnecterBuilder {
             host: host.into(),
             bot_token: token.into(),
         };
 
-        EventLoopBuilder { connecter }.build().await
+        EventLoopBuilder {
+            connecter,
+            reconnect: ReconnectPolicy::default(),
+        }
+        .build()
+        .await
+    }
+
+    /// Read the next message, reconnecting when the connection is lost.
+    /// Pings and Close frames are handled here and never reach the handler.
+    /// Fails once the reconnect policy gives up, or on an unrecoverable close.
+    async fn next_message(&mut self) -> Result<Message, EventLoopError> {
+        loop {
+            let frame = self.connecter.ws_read.next().await;
+            if let Some(message) = self.on_frame(frame).await? {
+                return Ok(message);
+            }
+        }
+    }
+
+    /// Answer a frame read from the websocket. Returns the message to hand to
+    /// the handler, or `None` for pings, Close frames and lost connections.
+    async fn on_frame(
+        &mut self,
+        frame: Option<Result<Message, Error>>,
+    ) -> Result<Option<Message>, EventLoopError> {
+        match frame {
+            Some(Ok(Message::Ping(_))) => {
+                if let Err(e) = self
+                    .connecter
+                    .ws_write
+                    .send(Message::Pong(Default::default()))
+                    .await
+                {
+                    println!("Error: {:?}", e);
+                    self.connecter.reconnect(&self.reconnect).await?;
+                }
+            }
+            Some(Ok(Message::Close(frame))) => {
+                let close = CloseEvent::from(frame);
+                println!("Closed by server: {}", close);
+
+                // flushes the close reply queued by tungstenite
+                let _ = self.connecter.ws_write.close().await;
+
+                if !close.is_recoverable() {
+                    return Err(EventLoopError::Closed(close));
+                }
+                self.connecter.reconnect(&self.reconnect).await?;
+            }
+            Some(Ok(message)) => return Ok(Some(message)),
+            Some(Err(e)) => {
+                println!("Error: {:?}", e);
+                self.connecter.reconnect(&self.reconnect).await?;
+            }
+            None => {
+                println!("Connection closed.");
+                self.connecter.reconnect(&self.reconnect).await?;
+            }
+        }
+
+        Ok(None)
     }
 
-    pub async fn run<Stats, F, Fut>(&mut self, stats: Stats, event_loop: F)
+    pub async fn run<Stats, F, Fut>(
+        &mut self,
+        stats: Stats,
+        event_loop: F,
+    ) -> Result<(), EventLoopError>
     where
         Stats: Send + Sync,
         F: Fn(Message, TraqApi, std::sync::Arc<Stats>) -> Fut,
@@ -43,62 +216,140 @@ impl EventLoop {
     {
         let stats = std::sync::Arc::new(stats);
 
-        let ws_read = &mut self.connecter.ws_read;
-        let ws_write = &mut self.connecter.ws_write;
-        let http_client = &self.connecter.http_client;
-
-        while let Some(message) = ws_read.next().await {
-            match message {
-                Ok(message) => {
-                    if let Message::Ping(_) = message {
-                        ws_write
-                            .send(Message::Pong(Default::default()))
-                            .await
-                            .unwrap();
-                        continue;
-                    } else {
-                        (event_loop)(message, http_client.clone(), stats.clone()).await;
-                    }
+        loop {
+            let message = self.next_message().await?;
+            let http_client = self.connecter.http_client.clone();
+
+            (event_loop)(message, http_client, stats.clone()).await;
+        }
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            shutdown: self.shutdown.clone(),
+        }
+    }
+
+    /// Like `run`, but each event is handled on a task of its own, so a slow
+    /// handler does not hold up reading. At most `max_handlers` handlers run at
+    /// a time. Events read while they are all taken wait for a slot, up to
+    /// `MAX_PENDING_EVENTS`; reading, and so answering pings, goes on meanwhile.
+    ///
+    /// Returns after a shutdown through `shutdown_handle`, or on a connection
+    /// error. Either way the handlers in flight are awaited first.
+    ///
+    /// # Panics
+    ///
+    /// If `max_handlers` is 0.
+    pub async fn run_concurrent<Stats, F, Fut>(
+        &mut self,
+        stats: Stats,
+        event_loop: F,
+        max_handlers: usize,
+    ) -> Result<(), EventLoopError>
+    where
+        Stats: Send + Sync + 'static,
+        F: Fn(Message, TraqApi, Arc<Stats>) -> Fut,
+        Fut: std::future::Future<Output = ()> + Send + 'static,
+    {
+        assert!(max_handlers > 0, "max_handlers must be positive");
+
+        let stats = Arc::new(stats);
+        let slots = Arc::new(Semaphore::new(max_handlers));
+        let mut handlers = JoinSet::new();
+        let mut pending = VecDeque::new();
+        let mut shutdown = self.shutdown.subscribe();
+
+        let result = loop {
+            // collect finished handlers so the set does not grow
+            while let Some(result) = handlers.try_join_next() {
+                if let Err(e) = result {
+                    println!("Handler failed: {}", e);
                 }
-                Err(e) => {
-                    // todo: implement error handling
-                    println!("Error: {:?}", e);
-                    break;
+            }
+
+            // a slot is only waited for while an event waits for one. the
+            // semaphore is never closed.
+            let frame = tokio::select! {
+                slot = slots.clone().acquire_owned(), if !pending.is_empty() => {
+                    let slot = slot.unwrap();
+                    let message = pending.pop_front().unwrap();
+                    let handler =
+                        (event_loop)(message, self.connecter.http_client.clone(), stats.clone());
+
+                    handlers.spawn(async move {
+                        let _slot = slot;
+                        handler.await;
+                    });
+                    continue;
+                }
+                frame = self.connecter.ws_read.next() => frame,
+                _ = shutdown.wait_for(|shutdown| *shutdown) => {
+                    let _ = self.connecter.ws_write.close().await;
+                    break Ok(());
                 }
+            };
+
+            match self.on_frame(frame).await {
+                Ok(Some(_)) if pending.len() >= MAX_PENDING_EVENTS => {
+                    println!(
+                        "Dropped event: {} event(s) wait for a handler",
+                        pending.len()
+                    );
+                }
+                Ok(Some(message)) => pending.push_back(message),
+                Ok(None) => {}
+                Err(e) => break Err(e),
+            }
+        };
+
+        if !pending.is_empty() {
+            println!("Dropped {} event(s) waiting for a handler.", pending.len());
+        }
+        println!("Waiting for {} handler(s)...", handlers.len());
+        while let Some(result) = handlers.join_next().await {
+            if let Err(e) = result {
+                println!("Handler failed: {}", e);
             }
         }
+
+        result
+    }
+
+    /// `run_concurrent` with the events parsed and passed to the handlers of the router.
+    pub async fn run_router<Stats>(
+        &mut self,
+        stats: Stats,
+        router: Router<Stats>,
+        max_handlers: usize,
+    ) -> Result<(), EventLoopError>
+    where
+        Stats: Send + Sync + 'static,
+    {
+        self.run_concurrent(
+            stats,
+            |message, api, stats| router.handle(message, api, stats),
+            max_handlers,
+        )
+        .await
     }
 
-    pub async fn run_blocking<Stats, F>(&mut self, stats: Stats, event_loop: F)
+    pub async fn run_blocking<Stats, F>(
+        &mut self,
+        stats: Stats,
+        event_loop: F,
+    ) -> Result<(), EventLoopError>
     where
         Stats: Send + Sync,
         F: Fn(Message, TraqApi, std::sync::Arc<Stats>),
     {
         let stats = std::sync::Arc::new(stats);
 
-        let ws_read = &mut self.connecter.ws_read;
-        let ws_write = &mut self.connecter.ws_write;
-        let http_client = &self.connecter.http_client;
-
-        while let Some(message) = ws_read.next().await {
-            match message {
-                Ok(message) => {
-                    if let Message::Ping(_) = message {
-                        ws_write
-                            .send(Message::Pong(Default::default()))
-                            .await
-                            .unwrap();
-                        continue;
-                    } else {
-                        (event_loop)(message, http_client.clone(), stats.clone());
-                    }
-                }
-                Err(e) => {
-                    // todo: implement error handling
-                    println!("Error: {:?}", e);
-                    break;
-                }
-            }
+        loop {
+            let message = self.next_message().await?;
+            let http_client = self.connecter.http_client.clone();
+
+            (event_loop)(message, http_client, stats.clone());
         }
     }
 }
@@ -0,0 +1,115 @@
+use std::{collections::HashMap, future::Future, sync::Arc};
+
+use futures::{future::BoxFuture, FutureExt};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::{
+    event::{self, Event, EventBody},
+    traq_api::TraqApi,
+};
+
+/// What a handler gets besides the payload.
+pub struct Context<S> {
+    pub api: TraqApi,
+    pub state: Arc<S>,
+    pub req_id: String,
+}
+
+impl<S> Clone for Context<S> {
+    fn clone(&self) -> Self {
+        Self {
+            api: self.api.clone(),
+            state: self.state.clone(),
+            req_id: self.req_id.clone(),
+        }
+    }
+}
+
+type Handler<S> = Box<dyn Fn(EventBody, Context<S>) -> BoxFuture<'static, ()> + Send + Sync>;
+
+/// Handlers keyed on the event type. Events without a handler are dropped.
+pub struct Router<S> {
+    handlers: HashMap<&'static str, Handler<S>>,
+    unknown: Option<Handler<S>>,
+}
+
+impl<S: Send + Sync + 'static> Default for Router<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+macro_rules! on {
+    ($($variant:ident($payload:ty) => $kind:literal $method:ident,)*) => {
+        $(
+            pub fn $method<F, Fut>(mut self, handler: F) -> Self
+            where
+                F: Fn($payload, Context<S>) -> Fut + Send + Sync + 'static,
+                Fut: Future<Output = ()> + Send + 'static,
+            {
+                self.handlers.insert(
+                    $kind,
+                    Box::new(move |body, cx| match body {
+                        EventBody::$variant(payload) => handler(payload, cx).boxed(),
+                        // handlers are looked up by kind
+                        _ => async {}.boxed(),
+                    }),
+                );
+                self
+            }
+        )*
+    };
+}
+
+impl<S: Send + Sync + 'static> Router<S> {
+    pub fn new() -> Self {
+        Self {
+            handlers: HashMap::new(),
+            unknown: None,
+        }
+    }
+
+    event::for_each_event!(on);
+
+    /// Handler of event types this crate does not know. Gets the type and the raw body.
+    pub fn on_unknown<F, Fut>(mut self, handler: F) -> Self
+    where
+        F: Fn(String, serde_json::Value, Context<S>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.unknown = Some(Box::new(move |body, cx| match body {
+            EventBody::Unknown { r#type, body } => handler(r#type, body, cx).boxed(),
+            _ => async {}.boxed(),
+        }));
+        self
+    }
+
+    /// Parse the frame and call the handler of its event type.
+    /// Frames other than text and events without a handler are dropped.
+    pub fn handle(&self, message: Message, api: TraqApi, state: Arc<S>) -> BoxFuture<'static, ()> {
+        let Message::Text(text) = message else {
+            return async {}.boxed();
+        };
+
+        let Event { req_id, body } = match Event::from_json(&text) {
+            Ok(event) => event,
+            Err(e) => {
+                println!("Failed to parse event: {}", e);
+                return async {}.boxed();
+            }
+        };
+
+        let handler = match &body {
+            EventBody::Unknown { r#type, .. } => {
+                println!("Unknown event: {}", r#type);
+                self.unknown.as_ref()
+            }
+            body => self.handlers.get(body.kind()),
+        };
+
+        match handler {
+            Some(handler) => handler(body, Context { api, state, req_id }),
+            None => async {}.boxed(),
+        }
+    }
+}
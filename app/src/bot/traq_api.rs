@@ -1,4 +1,8 @@
-use reqwest::Response;
+use reqwest::{RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+
+pub mod models;
+pub use models::*;
 
 #[derive(Clone)]
 pub struct TraqApi {
@@ -36,7 +40,7 @@ impl TraqApi {
         channel_id: impl AsRef<str>,
         message: impl AsRef<str>,
         embed: bool,
-    ) -> Result<Response, reqwest::Error> {
+    ) -> Result<Message, TraqApiError> {
         let url = format!(
             "{}/channels/{}/messages",
             self.url_api_prefix,
@@ -48,6 +52,181 @@ impl TraqApi {
             "embed": embed,
         });
 
-        self.http_client.post(&url).json(&body).send().await
+        Self::json(self.http_client.post(&url).json(&body)).await
+    }
+
+    pub async fn edit_message(
+        &self,
+        message_id: impl AsRef<str>,
+        message: impl AsRef<str>,
+        embed: bool,
+    ) -> Result<(), TraqApiError> {
+        let url = format!("{}/messages/{}", self.url_api_prefix, message_id.as_ref());
+
+        let body = serde_json::json!({
+            "content": message.as_ref(),
+            "embed": embed,
+        });
+
+        Self::send(self.http_client.put(&url).json(&body)).await?;
+        Ok(())
+    }
+
+    pub async fn delete_message(&self, message_id: impl AsRef<str>) -> Result<(), TraqApiError> {
+        let url = format!("{}/messages/{}", self.url_api_prefix, message_id.as_ref());
+
+        Self::send(self.http_client.delete(&url)).await?;
+        Ok(())
+    }
+
+    pub async fn get_message(&self, message_id: impl AsRef<str>) -> Result<Message, TraqApiError> {
+        let url = format!("{}/messages/{}", self.url_api_prefix, message_id.as_ref());
+
+        Self::json(self.http_client.get(&url)).await
+    }
+
+    /// One page of the messages of the channel. Use `MessageQuery::offset` or
+    /// `until` to read further while `MessagePage::more` is set.
+    pub async fn list_messages(
+        &self,
+        channel_id: impl AsRef<str>,
+        query: &MessageQuery,
+    ) -> Result<MessagePage, TraqApiError> {
+        let url = format!(
+            "{}/channels/{}/messages",
+            self.url_api_prefix,
+            channel_id.as_ref()
+        );
+
+        let response = Self::send(self.http_client.get(&url).query(query)).await?;
+
+        let more = response
+            .headers()
+            .get("X-TRAQ-MORE")
+            .is_some_and(|more| more == "true");
+        let messages = response.json().await.map_err(TraqApiError::Decode)?;
+
+        Ok(MessagePage { messages, more })
+    }
+}
+
+/// stamps
+impl TraqApi {
+    /// Stamp the message. `count` adds to the count of the bot's own stamp.
+    pub async fn add_stamp(
+        &self,
+        message_id: impl AsRef<str>,
+        stamp_id: impl AsRef<str>,
+        count: u32,
+    ) -> Result<(), TraqApiError> {
+        let url = format!(
+            "{}/messages/{}/stamps/{}",
+            self.url_api_prefix,
+            message_id.as_ref(),
+            stamp_id.as_ref()
+        );
+
+        let body = serde_json::json!({ "count": count });
+
+        Self::send(self.http_client.post(&url).json(&body)).await?;
+        Ok(())
+    }
+
+    pub async fn remove_stamp(
+        &self,
+        message_id: impl AsRef<str>,
+        stamp_id: impl AsRef<str>,
+    ) -> Result<(), TraqApiError> {
+        let url = format!(
+            "{}/messages/{}/stamps/{}",
+            self.url_api_prefix,
+            message_id.as_ref(),
+            stamp_id.as_ref()
+        );
+
+        Self::send(self.http_client.delete(&url)).await?;
+        Ok(())
+    }
+
+    /// Every stamp of the server, unicode ones included.
+    pub async fn list_stamps(&self) -> Result<Vec<StampInfo>, TraqApiError> {
+        let url = format!("{}/stamps", self.url_api_prefix);
+
+        Self::json(self.http_client.get(&url)).await
+    }
+}
+
+/// users
+impl TraqApi {
+    /// The user of the name, if there is one.
+    pub async fn find_user(&self, name: impl AsRef<str>) -> Result<Option<UserInfo>, TraqApiError> {
+        let url = format!("{}/users", self.url_api_prefix);
+
+        let users: Vec<UserInfo> =
+            Self::json(self.http_client.get(&url).query(&[("name", name.as_ref())])).await?;
+        Ok(users.into_iter().next())
+    }
+}
+
+/// requests
+impl TraqApi {
+    /// Send the request and fail on an error status.
+    async fn send(request: RequestBuilder) -> Result<reqwest::Response, TraqApiError> {
+        let response = request.send().await.map_err(TraqApiError::Request)?;
+
+        let status = response.status();
+        if status.is_client_error() || status.is_server_error() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(TraqApiError::Status { status, body });
+        }
+
+        Ok(response)
+    }
+
+    async fn json<T: DeserializeOwned>(request: RequestBuilder) -> Result<T, TraqApiError> {
+        Self::send(request)
+            .await?
+            .json()
+            .await
+            .map_err(TraqApiError::Decode)
+    }
+}
+
+#[derive(Debug)]
+pub enum TraqApiError {
+    /// The request could not be sent or the response not read.
+    Request(reqwest::Error),
+    /// traQ answered with an error status.
+    Status { status: StatusCode, body: String },
+    /// The response body is not the expected model.
+    Decode(reqwest::Error),
+}
+
+impl TraqApiError {
+    /// The HTTP status, if traQ answered with an error status.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            TraqApiError::Status { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for TraqApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TraqApiError::Request(e) => write!(f, "request failed: {}", e),
+            TraqApiError::Status { status, body } => write!(f, "{}: {}", status, body),
+            TraqApiError::Decode(e) => write!(f, "invalid response: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TraqApiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TraqApiError::Request(e) | TraqApiError::Decode(e) => Some(e),
+            TraqApiError::Status { .. } => None,
+        }
     }
 }
@@ -0,0 +1,84 @@
+use serde::{Deserialize, Serialize};
+
+use crate::event::Stamp;
+
+/// A message as returned by the REST API.
+///
+/// Unlike `event::MessageBody` of the events, the author is only an id and
+/// the content is not rendered to plain text.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Message {
+    pub id: String,
+    pub user_id: String,
+    pub channel_id: String,
+    pub content: String,
+    pub created_at: String,
+    pub updated_at: String,
+    pub pinned: bool,
+    pub stamps: Vec<Stamp>,
+    pub thread_id: Option<String>,
+}
+
+/// Query of `TraqApi::list_messages`. Unset fields use the server defaults.
+#[derive(Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageQuery {
+    /// Max number of messages, 1 to 200.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u32>,
+    /// Only messages created after this time, RFC 3339.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since: Option<String>,
+    /// Only messages created before this time, RFC 3339.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub until: Option<String>,
+    /// Include the messages created exactly at `since` / `until`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inclusive: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order: Option<Order>,
+}
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    Asc,
+    Desc,
+}
+
+pub struct MessagePage {
+    pub messages: Vec<Message>,
+    /// There are more messages beyond this page.
+    pub more: bool,
+}
+
+/// A stamp of the server. Not to be confused with `event::Stamp`, a stamp
+/// put on a message.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StampInfo {
+    pub id: String,
+    pub name: String,
+    pub creator_id: String,
+    pub created_at: String,
+    pub updated_at: String,
+    pub file_id: String,
+    pub is_unicode: bool,
+}
+
+/// A user as returned by the REST API.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserInfo {
+    pub id: String,
+    pub name: String,
+    pub display_name: String,
+    pub icon_file_id: String,
+    pub bot: bool,
+    /// 0: deactivated, 1: active, 2: suspended
+    pub state: i32,
+    pub updated_at: String,
+}
@@ -1,12 +1,24 @@
+use std::{
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
 use futures::{
     stream::{SplitSink, SplitStream},
     StreamExt,
 };
 use tokio::net::TcpStream;
-use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use tokio_tungstenite::{
+    tungstenite::{Error, Message},
+    MaybeTlsStream, WebSocketStream,
+};
 
 use crate::traq_api::TraqApi;
 
+type WsRead = SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>;
+type WsWrite = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>;
+
+#[derive(Clone)]
 pub struct TraqServerConnecterBuilder {
     /// The host of the Traq server.
     pub host: String,
@@ -15,7 +27,74 @@ pub struct TraqServerConnecterBuilder {
 }
 
 impl TraqServerConnecterBuilder {
-    pub async fn build(self) -> TraqServerConnecter {
+    pub async fn build(self) -> Result<TraqServerConnecter, Error> {
+        let (ws_read, ws_write) = self.connect().await?;
+
+        Ok(TraqServerConnecter {
+            ws_read,
+            ws_write,
+            http_client: TraqApi::new(&self.host, &self.bot_token),
+            builder: self,
+        })
+    }
+
+    /// Like `build`, but a failed connection is retried as the policy says.
+    pub async fn build_with_retry(
+        self,
+        policy: &ReconnectPolicy,
+    ) -> Result<TraqServerConnecter, Error> {
+        let (ws_read, ws_write) = match self.connect().await {
+            Ok(ws) => ws,
+            Err(e) => {
+                println!("Connect failed: {}", e);
+                self.connect_with_backoff(policy).await?
+            }
+        };
+
+        Ok(TraqServerConnecter {
+            ws_read,
+            ws_write,
+            http_client: TraqApi::new(&self.host, &self.bot_token),
+            builder: self,
+        })
+    }
+
+    /// Connect, waiting before each attempt as the policy says. Fails with the
+    /// last error once the attempts run out.
+    async fn connect_with_backoff(
+        &self,
+        policy: &ReconnectPolicy,
+    ) -> Result<(WsRead, WsWrite), Error> {
+        let mut delay = policy.initial_delay;
+        let mut attempt = 0;
+
+        loop {
+            attempt += 1;
+
+            let wait = policy.jitter(delay);
+            println!(
+                "Reconnecting in {}ms (attempt {})...",
+                wait.as_millis(),
+                attempt
+            );
+            tokio::time::sleep(wait).await;
+
+            match self.connect().await {
+                Ok(ws) => return Ok(ws),
+                Err(e) => {
+                    println!("Reconnect failed: {}", e);
+
+                    if policy.max_attempts.is_some_and(|max| attempt >= max) {
+                        return Err(e);
+                    }
+
+                    delay = delay.mul_f64(policy.multiplier).min(policy.max_delay);
+                }
+            }
+        }
+    }
+
+    async fn connect(&self) -> Result<(WsRead, WsWrite), Error> {
         let wss_url = format!("wss://{}/api/v3/bots/ws", &self.host);
         let authorization_value = format!("{} {}", "Bearer", self.bot_token);
 
@@ -33,24 +112,71 @@ impl TraqServerConnecterBuilder {
             )
             .uri(wss_url)
             .header("Authorization", &authorization_value)
-            .body(())
-            .unwrap();
+            .body(())?;
 
-        let (ws_stream, _) = tokio_tungstenite::connect_async(ws_request).await.unwrap();
+        let (ws_stream, _) = tokio_tungstenite::connect_async(ws_request).await?;
 
         let (write, read) = ws_stream.split();
 
-        TraqServerConnecter {
-            ws_read: read,
-            ws_write: write,
-            http_client: TraqApi::new(self.host, self.bot_token),
-        }
+        Ok((read, write))
     }
 }
 
 pub struct TraqServerConnecter {
-    pub(crate) ws_read: SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>,
-    pub(crate) ws_write: SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>,
+    pub(crate) ws_read: WsRead,
+    pub(crate) ws_write: WsWrite,
 
     pub(crate) http_client: TraqApi,
+
+    builder: TraqServerConnecterBuilder,
+}
+
+impl TraqServerConnecter {
+    /// Drop the current connection and connect again, waiting between attempts
+    /// as the policy says. Fails with the last error once the attempts run out.
+    pub async fn reconnect(&mut self, policy: &ReconnectPolicy) -> Result<(), Error> {
+        let (ws_read, ws_write) = self.builder.connect_with_backoff(policy).await?;
+        self.ws_read = ws_read;
+        self.ws_write = ws_write;
+        println!("Reconnected.");
+
+        Ok(())
+    }
+}
+
+/// Exponential backoff of reconnect attempts.
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    /// Delay before the first attempt.
+    pub initial_delay: Duration,
+    /// Upper bound of the delay.
+    pub max_delay: Duration,
+    /// Factor the delay grows by after each failed attempt.
+    pub multiplier: f64,
+    /// Attempts in a row before giving up. `None` retries forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(60),
+            multiplier: 2.0,
+            max_attempts: Some(10),
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// A random delay in `[delay / 2, delay]`, so restarted bots do not reconnect in step.
+    fn jitter(&self, delay: Duration) -> Duration {
+        // RandomState is seeded randomly, which is enough here
+        let random = std::collections::hash_map::RandomState::new()
+            .build_hasher()
+            .finish();
+        let ratio = random as f64 / u64::MAX as f64;
+
+        delay.mul_f64(0.5 + ratio / 2.0)
+    }
 }
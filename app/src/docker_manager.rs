@@ -1,15 +1,37 @@
-use std::{collections::HashMap, vec};
-
-use bollard::{container, image, Docker};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use bollard::{container, Docker};
 use futures::StreamExt;
-use tokio::io::AsyncReadExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use traq_python_bot::create_tar_archive;
 
+mod cancel;
+pub use cancel::CancelToken;
+mod labels;
+pub use labels::RunOwner;
+mod language;
+pub use language::*;
+mod pool;
+pub use pool::PoolStats;
+use pool::{Sandbox, SandboxPool};
+mod resource_limits;
+pub use resource_limits::*;
+
 pub struct DockerManagerBuilder {
     // docker client
     docker: Docker,
     // configs
     tar_dir: String,
+    sandbox_dir: String,
+    limits: ResourceLimits,
+    languages: LanguageRegistry,
+    pool_size: usize,
     // docker files
     docker_files: HashMap<String, DockerFiles>,
 }
@@ -23,16 +45,40 @@ impl DockerManager {
     pub fn builder(
         tar_dir: impl Into<String>,
         sandbox_dir: impl Into<String>,
-    ) -> Result<DockerManagerBuilder, Box<dyn std::error::Error>> {
+    ) -> Result<DockerManagerBuilder, Box<dyn std::error::Error + Send + Sync>> {
         Ok(DockerManagerBuilder {
             docker: Docker::connect_with_local_defaults()?,
             tar_dir: tar_dir.into(),
+            sandbox_dir: sandbox_dir.into(),
+            limits: ResourceLimits::default(),
+            languages: LanguageRegistry::default(),
+            pool_size: 0,
             docker_files: HashMap::new(),
         })
     }
 }
 
 impl DockerManagerBuilder {
+    /// Resource limits applied to every container the manager creates.
+    pub fn resource_limits(mut self, limits: ResourceLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Languages `DockerManager::run_code` can run. Their images are pulled on build.
+    /// A language whose image fails to pull is left out.
+    pub fn languages(mut self, languages: LanguageRegistry) -> Self {
+        self.languages = languages;
+        self
+    }
+
+    /// Number of pre-created sandboxes kept per language. 0 (default) disables the pool.
+    pub fn pool_size(mut self, pool_size: usize) -> Self {
+        self.pool_size = pool_size;
+        self
+    }
+
+    #[allow(dead_code)]
     pub fn docker_files(
         mut self,
         name: impl Into<String> + AsRef<str>,
@@ -50,22 +96,102 @@ impl DockerManagerBuilder {
         self
     }
 
-    pub async fn build(self) -> Result<DockerManager, Box<dyn std::error::Error>> {
+    pub async fn build(self) -> Result<DockerManager, Box<dyn std::error::Error + Send + Sync>> {
         let DockerManagerBuilder {
             docker,
             tar_dir,
+            sandbox_dir,
+            limits,
+            languages,
+            pool_size,
             docker_files,
         } = self;
 
+        // a language whose image is not available does not stop the bot
+        let mut available = LanguageRegistry::new();
+        for language in languages.iter() {
+            match pull_image(&docker, &language.image).await {
+                Ok(()) => available.add(language.clone()),
+                Err(e) => println!(
+                    "failed to pull {}: {}. {} is not available.",
+                    language.image, e, language.name
+                ),
+            }
+        }
+        let languages = available;
+
+        let instance = uuid::Uuid::now_v7().to_string();
+        println!("instance: {}", instance);
+
         let mut image_ids = HashMap::new();
         for (name, dockerfile) in docker_files {
-            let docker_image = dockerfile.build_image(&docker, &name, &tar_dir).await?;
+            let docker_image = dockerfile
+                .build_image(&docker, &name, &tar_dir, &instance)
+                .await?;
             println!("DockerManagerBuilder build: {}", name);
             image_ids.insert(name, docker_image);
         }
 
-        Ok(DockerManager { docker, image_ids })
+        let pool = Arc::new(SandboxPool::new(
+            docker.clone(),
+            instance.clone(),
+            limits.clone(),
+            sandbox_dir.clone(),
+            pool_size,
+        ));
+
+        let manager = DockerManager {
+            docker,
+            instance,
+            sandbox_dir,
+            limits,
+            languages,
+            pool,
+            image_ids,
+        };
+
+        // before the pool creates the sandbox dirs of this instance
+        let report = manager.reap().await;
+        println!("reaped {} orphaned object(s).", report.removed);
+        for (id, e) in &report.failed {
+            println!("failed to reap {}: {}", id, e);
+        }
+
+        for language in manager.languages.iter() {
+            manager.pool.replenish(language);
+        }
+
+        Ok(manager)
+    }
+}
+
+/// Pull the image unless it is already present.
+async fn pull_image(
+    docker: &Docker,
+    image: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if docker.inspect_image(image).await.is_ok() {
+        return Ok(());
+    }
+
+    println!("pulling image: {}", image);
+
+    let mut pull_stream = docker.create_image(
+        Some(bollard::image::CreateImageOptions {
+            from_image: image,
+            ..Default::default()
+        }),
+        None,
+        None,
+    );
+
+    while let Some(result) = pull_stream.next().await {
+        result?;
     }
+
+    println!("image pulled: {}", image);
+
+    Ok(())
 }
 
 impl DockerFiles {
@@ -74,7 +200,8 @@ impl DockerFiles {
         docker: &Docker,
         name: impl AsRef<str>,
         tar_dir: impl AsRef<str>,
-    ) -> Result<DockerImage, Box<dyn std::error::Error>> {
+        instance: &str,
+    ) -> Result<DockerImage, Box<dyn std::error::Error + Send + Sync>> {
         // make tar file and reed it
         let tar_file_name = format!("{}/{}.tar", tar_dir.as_ref(), name.as_ref());
 
@@ -94,6 +221,7 @@ impl DockerFiles {
         let build_image_options = bollard::image::BuildImageOptions {
             dockerfile: self.dockerfile.as_str(),
             t: &name_tug,
+            labels: labels::labels(instance, None, None),
             ..Default::default()
         };
 
@@ -133,9 +261,16 @@ impl DockerFiles {
 
 pub struct DockerManager {
     docker: Docker,
+    /// Id of this bot process, labelled on every object it creates.
+    instance: String,
+    sandbox_dir: String,
+    limits: ResourceLimits,
+    languages: LanguageRegistry,
+    pool: Arc<SandboxPool>,
     image_ids: HashMap<String, DockerImage>,
 }
 
+#[allow(dead_code)]
 struct DockerImage {
     image_name_tug: String,
     image_id: String,
@@ -146,16 +281,234 @@ pub struct RunResult {
     pub std_output: String,
     pub std_error: String,
     pub time: tokio::time::Duration,
+    /// Exit code of the container's main process.
+    pub exit_code: Option<i64>,
+    /// Why the run ended.
+    pub termination: Termination,
+    /// Peak memory usage in bytes, if the stats stream reported any.
+    pub peak_memory: Option<u64>,
+    /// The resource limit the run ran into, if any.
+    pub limit_exceeded: Option<LimitExceeded>,
+    /// The compile step, for compiled languages.
+    pub compile: Option<CompileResult>,
+}
+
+#[derive(Debug)]
+pub struct CompileResult {
+    /// Compiler output, stdout followed by stderr.
+    pub diagnostics: String,
+    pub time: tokio::time::Duration,
+    pub exit_code: Option<i64>,
+    pub termination: Termination,
+    pub limit_exceeded: Option<LimitExceeded>,
+}
+
+impl RunResult {
+    /// The program exited by itself with code 0.
+    pub fn success(&self) -> bool {
+        self.termination == Termination::Exited && self.exit_code == Some(0)
+    }
+}
+
+impl CompileResult {
+    /// The compiler exited by itself with code 0.
+    pub fn success(&self) -> bool {
+        self.termination == Termination::Exited && self.exit_code == Some(0)
+    }
+}
+
+impl From<RunResult> for CompileResult {
+    fn from(result: RunResult) -> Self {
+        Self {
+            diagnostics: result.std_output + &result.std_error,
+            time: result.time,
+            exit_code: result.exit_code,
+            termination: result.termination,
+            limit_exceeded: result.limit_exceeded,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Termination {
+    /// The program exited by itself. See `RunResult::exit_code` for the status.
+    Exited,
+    /// The program was killed after exceeding the time limit.
+    TimedOut,
+    /// The program was killed by the OOM killer.
+    OomKilled,
+    /// The program was killed by the signal.
+    Signaled(i64),
+    /// Docker failed to run the program.
+    InternalError(String),
+    /// The program did not run because it failed to compile.
+    CompileError,
+    /// The run was cancelled through its `CancelToken`.
+    Cancelled,
+}
+
+impl std::fmt::Display for Termination {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Termination::Exited => write!(f, "exited"),
+            Termination::TimedOut => write!(f, "time limit exceeded"),
+            Termination::OomKilled => write!(f, "killed (out of memory)"),
+            Termination::Signaled(signal) => write!(f, "killed by signal {}", signal),
+            Termination::InternalError(e) => write!(f, "internal error: {}", e),
+            Termination::CompileError => write!(f, "compile error"),
+            Termination::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+/// Outcome of removing docker objects in bulk.
+#[derive(Debug, Default)]
+pub struct RemoveReport {
+    pub removed: usize,
+    /// (id, error) of the objects that could not be removed.
+    pub failed: Vec<(String, String)>,
+}
+
+/// How a finished container ended, read from `inspect_container`.
+struct ExitStatus {
+    exit_code: Option<i64>,
+    termination: Termination,
+    peak_memory: Option<u64>,
+    limit_exceeded: Option<LimitExceeded>,
+}
+
+/// Peak resource usage of a running container, sampled from the stats stream.
+struct UsageMonitor {
+    peak_pids: Arc<AtomicU64>,
+    peak_memory: Arc<AtomicU64>,
+    handle: tokio::task::JoinHandle<()>,
+}
+
+struct Usage {
+    peak_pids: u64,
+    peak_memory: Option<u64>,
+}
+
+impl UsageMonitor {
+    fn start(docker: &Docker, container_id: &str) -> Self {
+        let peak_pids = Arc::new(AtomicU64::new(0));
+        let peak_memory = Arc::new(AtomicU64::new(0));
+
+        let mut stats = docker.stats(
+            container_id,
+            Some(bollard::container::StatsOptions {
+                stream: true,
+                one_shot: false,
+            }),
+        );
+
+        let handle = tokio::spawn({
+            let peak_pids = peak_pids.clone();
+            let peak_memory = peak_memory.clone();
+            async move {
+                while let Some(Ok(stats)) = stats.next().await {
+                    if let Some(current) = stats.pids_stats.current {
+                        peak_pids.fetch_max(current, Ordering::Relaxed);
+                    }
+                    // cgroup v1 reports max_usage, v2 only the current usage
+                    let memory = stats
+                        .memory_stats
+                        .max_usage
+                        .max(stats.memory_stats.usage)
+                        .unwrap_or(0);
+                    peak_memory.fetch_max(memory, Ordering::Relaxed);
+                }
+            }
+        });
+
+        Self {
+            peak_pids,
+            peak_memory,
+            handle,
+        }
+    }
+
+    fn stop(self) -> Usage {
+        self.handle.abort();
+        let peak_memory = self.peak_memory.load(Ordering::Relaxed);
+        Usage {
+            peak_pids: self.peak_pids.load(Ordering::Relaxed),
+            peak_memory: (peak_memory > 0).then_some(peak_memory),
+        }
+    }
 }
 
 impl DockerManager {
+    /// Wait for the container to exit and read how it ended.
+    /// `killed` is why the manager killed the container, if it did.
+    /// Must be called before the container is removed.
+    async fn exit_status(
+        &self,
+        container_id: &str,
+        monitor: UsageMonitor,
+        killed: Option<Termination>,
+    ) -> Result<ExitStatus, Box<dyn std::error::Error + Send + Sync>> {
+        // a non-zero exit code is reported as an error by bollard. ignore it.
+        let _ = self
+            .docker
+            .wait_container::<String>(container_id, None)
+            .collect::<Vec<_>>()
+            .await;
+
+        let usage = monitor.stop();
+
+        let state = self
+            .docker
+            .inspect_container(container_id, None)
+            .await?
+            .state
+            .unwrap_or_default();
+
+        let exit_code = state.exit_code;
+        let oom_killed = state.oom_killed.unwrap_or(false);
+
+        let termination = if let Some(killed) = killed {
+            killed
+        } else if oom_killed {
+            Termination::OomKilled
+        } else if let Some(error) = state.error.filter(|e| !e.is_empty()) {
+            Termination::InternalError(error)
+        } else {
+            match exit_code {
+                // docker-init and shells report a signal as 128 + n
+                Some(code) if code > 128 => Termination::Signaled(code - 128),
+                _ => Termination::Exited,
+            }
+        };
+
+        let limit_exceeded = if oom_killed {
+            Some(LimitExceeded::Memory {
+                limit: self.limits.memory,
+            })
+        } else if self.limits.pids_limit > 0 && usage.peak_pids >= self.limits.pids_limit as u64 {
+            Some(LimitExceeded::Pids {
+                limit: self.limits.pids_limit,
+            })
+        } else {
+            None
+        };
+
+        Ok(ExitStatus {
+            exit_code,
+            termination,
+            peak_memory: usage.peak_memory,
+            limit_exceeded,
+        })
+    }
+
     // Build docker image from dockerfile and execute f with DockerContainer.
     // Then remove the container after f is executed.
+    #[allow(dead_code)]
     pub async fn run_image(
         &self,
         name: impl AsRef<str> + Into<String>,
         args: Vec<impl AsRef<str>>,
-    ) -> Result<RunResult, Box<dyn std::error::Error>> {
+    ) -> Result<RunResult, Box<dyn std::error::Error + Send + Sync>> {
         let image = self
             .image_ids
             .get(name.as_ref())
@@ -167,6 +520,7 @@ impl DockerManager {
             args.iter().map(|arg| arg.as_ref()).collect::<Vec<_>>()
         );
 
+        let run_id = uuid::Uuid::now_v7().to_string();
         let container_config = container::Config {
             image: Some(image.image_name_tug.as_str()),
             cmd: Some(args.iter().map(|arg| arg.as_ref()).collect()),
@@ -174,10 +528,12 @@ impl DockerManager {
             attach_stdin: Some(true),
             attach_stdout: Some(true),
             attach_stderr: Some(true),
+            host_config: Some(self.limits.host_config()),
+            labels: Some(labels::labels(&self.instance, Some(&run_id), None)),
             ..Default::default()
         };
 
-        let container_name = format!("botpy-{}-{}", name.as_ref(), uuid::Uuid::now_v7());
+        let container_name = format!("botpy-{}-{}", name.as_ref(), run_id);
 
         let container = self
             .docker
@@ -196,6 +552,8 @@ impl DockerManager {
             .start_container::<&str>(&container_name, None)
             .await?;
 
+        let monitor = UsageMonitor::start(&self.docker, &container_id);
+
         // log
 
         let mut logs = self
@@ -205,7 +563,7 @@ impl DockerManager {
                 Some(bollard::container::LogsOptions::<String> {
                     stdout: true,
                     stderr: true,
-                    follow: false,
+                    follow: true,
                     ..Default::default()
                 }),
             )
@@ -213,7 +571,7 @@ impl DockerManager {
 
         let mut std_output = String::new();
         let mut std_error = String::new();
-        let timeout = tokio::time::Duration::from_secs(5);
+        let mut timed_out = false;
         let start_time = tokio::time::Instant::now();
 
         tokio::select! {
@@ -227,18 +585,29 @@ impl DockerManager {
                             std_error.push_str(std::str::from_utf8(&message)?);
                         },
                         Err(e) => {
-                            return Err::<(), Box<dyn std::error::Error>>(e.into());
+                            return Err::<(), Box<dyn std::error::Error + Send + Sync>>(e.into());
                         }
                         _ => {}
                     }
                 }
                 Ok(())
             } => res?,
-            _ = tokio::time::sleep(timeout) => {},
+            _ = tokio::time::sleep(self.limits.time_limit) => {
+                self.kill(&container_id).await?;
+                timed_out = true;
+            },
         }
 
         let run_time = start_time.elapsed();
 
+        let exit_status = self
+            .exit_status(
+                &container_id,
+                monitor,
+                timed_out.then_some(Termination::TimedOut),
+            )
+            .await?;
+
         // stop and remove container
 
         // let inspect_result = self
@@ -271,222 +640,536 @@ impl DockerManager {
             std_output,
             std_error,
             time: run_time,
+            exit_code: exit_status.exit_code,
+            termination: exit_status.termination,
+            peak_memory: exit_status.peak_memory,
+            limit_exceeded: exit_status.limit_exceeded,
+            compile: None,
         })
     }
 
     // run docker hello-world
-    pub async fn hello(&self) -> Result<RunResult, Box<dyn std::error::Error>> {
+    pub async fn hello(&self) -> Result<RunResult, Box<dyn std::error::Error + Send + Sync>> {
+        pull_image(&self.docker, "hello-world:latest").await?;
+
         // create and start container
+        let run_id = uuid::Uuid::now_v7().to_string();
         let container_config = container::Config {
             image: Some("hello-world"),
             tty: Some(false),
             attach_stdin: Some(true),
             attach_stdout: Some(true),
             attach_stderr: Some(true),
+            host_config: Some(self.limits.host_config()),
+            labels: Some(labels::labels(&self.instance, Some(&run_id), None)),
             ..Default::default()
         };
 
+        let container_name = format!("botpy-hello-{}", run_id);
+
         let container = self
             .docker
-            .create_container::<&str, &str>(None, container_config)
-            .await
-            .unwrap();
+            .create_container::<&str, &str>(
+                Some(bollard::container::CreateContainerOptions::<&str> {
+                    name: container_name.as_str(),
+                    ..Default::default()
+                }),
+                container_config,
+            )
+            .await?;
 
         let container_id = container.id;
 
+        let result = async {
+            let timer = tokio::time::Instant::now();
+
+            self.docker
+                .start_container::<&str>(&container_id, None)
+                .await?;
+
+            let monitor = UsageMonitor::start(&self.docker, &container_id);
+
+            // log
+
+            let mut logs = self
+                .docker
+                .logs::<String>(
+                    &container_id,
+                    Some(bollard::container::LogsOptions::<String> {
+                        stdout: true,
+                        stderr: true,
+                        follow: true,
+                        ..Default::default()
+                    }),
+                )
+                .fuse();
+
+            let mut std_output = String::new();
+            let mut std_error = String::new();
+
+            while let Some(log) = logs.next().await {
+                match log? {
+                    container::LogOutput::StdOut { message } => {
+                        std_output.push_str(&String::from_utf8_lossy(&message));
+                    }
+                    container::LogOutput::StdErr { message } => {
+                        std_error.push_str(&String::from_utf8_lossy(&message));
+                    }
+                    _ => {}
+                }
+            }
+
+            let time = timer.elapsed();
+
+            let exit_status = self.exit_status(&container_id, monitor, None).await?;
+
+            Ok(RunResult {
+                std_output,
+                std_error,
+                time,
+                exit_code: exit_status.exit_code,
+                termination: exit_status.termination,
+                peak_memory: exit_status.peak_memory,
+                limit_exceeded: exit_status.limit_exceeded,
+                compile: None,
+            })
+        }
+        .await;
+
+        // stop and remove container, also when the run failed
+        if let Err(e) = self.rm_container(&container_id).await {
+            println!("failed to remove {}: {}", container_id, e);
+        }
+
+        result
+    }
+
+    /// Find the language by its fence tag.
+    pub fn language(&self, tag: impl AsRef<str>) -> Option<&Language> {
+        self.languages.get(tag)
+    }
+
+    pub fn languages(&self) -> &LanguageRegistry {
+        &self.languages
+    }
+
+    pub fn pool_stats(&self) -> PoolStats {
+        self.pool.stats()
+    }
+
+    /// Run the snippet in a sandbox of its own.
+    /// For compiled languages the program only runs if it compiled.
+    /// Cancelling the token kills the running container.
+    pub async fn run_code(
+        &self,
+        language: &Language,
+        code: impl AsRef<str>,
+        args: Vec<impl AsRef<str>>,
+        stdin: Option<String>,
+        owner: &RunOwner,
+        cancel: &CancelToken,
+    ) -> Result<RunResult, Box<dyn std::error::Error + Send + Sync>> {
+        let args = args
+            .iter()
+            .map(|arg| arg.as_ref().to_string())
+            .collect::<Vec<_>>();
+
+        let sandbox = self.pool.acquire(language, &args, owner).await?;
+
+        println!(
+            "run {}: {} by user {} in channel {}",
+            sandbox.id, language.name, owner.user_id, owner.channel_id
+        );
+
+        let result = self
+            .run_in_sandbox(language, &sandbox, code.as_ref(), stdin, cancel)
+            .await;
+
+        self.pool.destroy(sandbox);
+
+        result
+    }
+
+    async fn run_in_sandbox(
+        &self,
+        language: &Language,
+        sandbox: &Sandbox,
+        code: &str,
+        stdin: Option<String>,
+        cancel: &CancelToken,
+    ) -> Result<RunResult, Box<dyn std::error::Error + Send + Sync>> {
+        tokio::fs::write(format!("{}/{}", sandbox.dir, language.source_file), code).await?;
+
+        let compile = match &sandbox.compile {
+            Some(container_id) => {
+                let compile: CompileResult = self
+                    .run_container(container_id, None, self.limits.compile_time_limit, cancel)
+                    .await?
+                    .into();
+
+                if !compile.success() {
+                    let termination = match compile.termination {
+                        Termination::Cancelled => Termination::Cancelled,
+                        _ => Termination::CompileError,
+                    };
+
+                    return Ok(RunResult {
+                        std_output: String::new(),
+                        std_error: String::new(),
+                        time: Default::default(),
+                        exit_code: None,
+                        termination,
+                        peak_memory: None,
+                        limit_exceeded: None,
+                        compile: Some(compile),
+                    });
+                }
+
+                Some(compile)
+            }
+            None => None,
+        };
+
+        let result = self
+            .run_container(&sandbox.run, stdin, self.limits.time_limit, cancel)
+            .await?;
+
+        Ok(RunResult { compile, ..result })
+    }
+
+    /// Start a created sandbox container and collect its output.
+    /// The container is left for the pool to remove.
+    async fn run_container(
+        &self,
+        container_id: &str,
+        stdin: Option<String>,
+        time_limit: tokio::time::Duration,
+        cancel: &CancelToken,
+    ) -> Result<RunResult, Box<dyn std::error::Error + Send + Sync>> {
+        if cancel.is_cancelled() {
+            return Ok(RunResult {
+                std_output: String::new(),
+                std_error: String::new(),
+                time: Default::default(),
+                exit_code: None,
+                termination: Termination::Cancelled,
+                peak_memory: None,
+                limit_exceeded: None,
+                compile: None,
+            });
+        }
+
+        // attach before start so the program sees the whole input
+        let attach = self
+            .docker
+            .attach_container::<String>(
+                container_id,
+                Some(bollard::container::AttachContainerOptions {
+                    stdin: Some(true),
+                    stream: Some(true),
+                    ..Default::default()
+                }),
+            )
+            .await?;
+
+        tokio::spawn(async move {
+            let mut input = attach.input;
+            // the program may exit without reading all of it
+            let _ = input.write_all(stdin.unwrap_or_default().as_bytes()).await;
+            // closes the container's stdin (stdin_once)
+            let _ = input.shutdown().await;
+        });
+
         let timer = tokio::time::Instant::now();
 
         self.docker
-            .start_container::<&str>(&container_id, None)
-            .await
-            .unwrap();
+            .start_container::<&str>(container_id, None)
+            .await?;
+
+        let monitor = UsageMonitor::start(&self.docker, container_id);
 
         // log
 
         let mut logs = self
             .docker
             .logs::<String>(
-                &container_id,
+                container_id,
                 Some(bollard::container::LogsOptions::<String> {
                     stdout: true,
                     stderr: true,
-                    follow: false,
+                    follow: true,
                     ..Default::default()
                 }),
             )
             .fuse();
 
-        let mut std_output = String::new();
-        let mut std_error = String::new();
-
-        while let Some(log) = logs.next().await {
-            match log {
-                Ok(container::LogOutput::StdOut { message }) => {
-                    std_output.push_str(std::str::from_utf8(&message).unwrap());
-                }
-                Ok(container::LogOutput::StdErr { message }) => {
-                    std_error.push_str(std::str::from_utf8(&message).unwrap());
-                }
-                Err(e) => {
-                    return Err(e.into());
+        // decode at the end. a chunk may split a multi-byte character.
+        let mut std_output = Vec::new();
+        let mut std_error = Vec::new();
+        // keep reading past the limit, so the program is not blocked on a full pipe
+        let output_limit = self.limits.output_limit;
+
+        let read_logs = async {
+            while let Some(log) = logs.next().await {
+                match log {
+                    Ok(container::LogOutput::StdOut { message }) => {
+                        push_capped(&mut std_output, &message, output_limit);
+                    }
+                    Ok(container::LogOutput::StdErr { message }) => {
+                        push_capped(&mut std_error, &message, output_limit);
+                    }
+                    Err(e) => {
+                        return Err::<(), Box<dyn std::error::Error + Send + Sync>>(e.into());
+                    }
+                    _ => {}
                 }
-                _ => {}
             }
-        }
+            Ok(())
+        };
 
-        let time = timer.elapsed();
+        let killed = tokio::select! {
+            res = tokio::time::timeout(time_limit, read_logs) => match res {
+                Ok(res) => {
+                    res?;
+                    None
+                }
+                Err(_) => Some(Termination::TimedOut),
+            },
+            _ = cancel.cancelled() => Some(Termination::Cancelled),
+        };
 
-        // stop and remove container
+        if killed.is_some() {
+            self.kill(container_id).await?;
+        }
 
-        self.docker
-            .stop_container(
-                &container_id,
-                Some(bollard::container::StopContainerOptions { t: 0 }),
-            )
-            .await
-            .unwrap();
+        let time = timer.elapsed();
 
-        self.docker
-            .remove_container(
-                &container_id,
-                Some(bollard::container::RemoveContainerOptions {
-                    force: true,
-                    ..Default::default()
-                }),
-            )
-            .await
-            .unwrap();
+        let exit_status = self.exit_status(container_id, monitor, killed).await?;
 
         Ok(RunResult {
-            std_output,
-            std_error,
+            std_output: String::from_utf8_lossy(&std_output).into_owned(),
+            std_error: String::from_utf8_lossy(&std_error).into_owned(),
             time,
+            exit_code: exit_status.exit_code,
+            termination: exit_status.termination,
+            peak_memory: exit_status.peak_memory,
+            limit_exceeded: exit_status.limit_exceeded,
+            compile: None,
         })
     }
 
-    pub async fn python3(
+    /// Kill a running container immediately.
+    /// A container that has already exited is not an error.
+    async fn kill(
         &self,
-        args: Vec<impl AsRef<str>>,
-        host_mount_dir_path: impl AsRef<str>,
-        container_code_file_name: impl AsRef<str>,
-        container_output_file_name: impl AsRef<str>,
-    ) -> Result<RunResult, Box<dyn std::error::Error>> {
-        let container_code_file_path = format!("/sandbox/{}", container_code_file_name.as_ref());
-        let container_output_file_path =
-            format!("/sandbox/{}", container_output_file_name.as_ref());
-
-        let cmd = [
-            "python3",
-            &container_code_file_path,
-            ">",
-            &container_output_file_path,
-        ]
-        .join(" ")
-        .to_string();
-
-        let python_image = std::env::var("PYTHON_IMAGE").unwrap();
+        container_id: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        match self
+            .docker
+            .kill_container(
+                container_id,
+                Some(bollard::container::KillContainerOptions { signal: "SIGKILL" }),
+            )
+            .await
+        {
+            Ok(())
+            | Err(bollard::errors::Error::DockerResponseServerError {
+                status_code: 409, ..
+            }) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
 
-        let container_config = container::Config {
-            image: Some(python_image.as_str()),
-            cmd: Some(vec!["sh", "-c", &cmd]),
-            tty: Some(true),
-            attach_stdin: Some(true),
-            attach_stdout: Some(true),
-            attach_stderr: Some(true),
-            network_disabled: Some(true),
-            host_config: Some(bollard::models::HostConfig {
-                binds: Some(vec![format!(
-                    "{}:/sandbox:rw",
-                    host_mount_dir_path.as_ref()
-                )]),
-                init: Some(true),
-                ..Default::default()
-            }),
-            ..Default::default()
+    /// Remove every container created by the bot, found by the `botpy-` name prefix.
+    /// Idle sandboxes of the pool are removed first. Running sandboxes are killed.
+    pub async fn rm_all_containers(
+        &self,
+    ) -> Result<RemoveReport, Box<dyn std::error::Error + Send + Sync>> {
+        let mut report = RemoveReport {
+            removed: self.pool.clear().await,
+            failed: Vec::new(),
         };
 
-        let container = self
+        let containers = self
             .docker
-            .create_container::<&str, &str>(None, container_config)
-            .await
-            .unwrap();
-
-        let container_id = container.id;
+            .list_containers(Some(bollard::container::ListContainersOptions::<&str> {
+                all: true,
+                filters: HashMap::from([("name", vec!["botpy-"])]),
+                ..Default::default()
+            }))
+            .await?;
 
-        let timer = tokio::time::Instant::now();
+        for container in containers {
+            // the name filter matches anywhere in the name
+            let owned = container
+                .names
+                .iter()
+                .flatten()
+                .any(|name| name.starts_with("/botpy-"));
+
+            let Some(id) = container.id.filter(|_| owned) else {
+                continue;
+            };
+
+            match self.rm_container(&id).await {
+                Ok(()) => report.removed += 1,
+                Err(e) => report.failed.push((id, e.to_string())),
+            }
+        }
 
-        self.docker
-            .start_container::<&str>(&container_id, None)
-            .await
-            .unwrap();
+        Ok(report)
+    }
 
-        // log
+    /// Remove every image built by the bot, found by the `botpy-` repository prefix.
+    pub async fn rm_all_images(
+        &self,
+    ) -> Result<RemoveReport, Box<dyn std::error::Error + Send + Sync>> {
+        let mut report = RemoveReport::default();
 
-        let mut logs = self
+        let images = self
             .docker
-            .logs::<String>(
-                &container_id,
-                Some(bollard::container::LogsOptions::<String> {
-                    stdout: true,
-                    stderr: true,
-                    follow: false,
-                    ..Default::default()
-                }),
-            )
-            .fuse();
+            .list_images(Some(bollard::image::ListImagesOptions::<&str> {
+                filters: HashMap::from([("reference", vec!["botpy-*"])]),
+                ..Default::default()
+            }))
+            .await?;
 
-        let mut std_output = String::new();
-        let mut std_error = String::new();
+        for image in images {
+            match self.rm_images(&image.id).await {
+                Ok(()) => report.removed += 1,
+                Err(e) => report.failed.push((image.id, e.to_string())),
+            }
+        }
 
-        while let Some(log) = logs.next().await {
-            match log {
-                Ok(container::LogOutput::StdErr { message }) => {
-                    std_error.push_str(std::str::from_utf8(&message).unwrap());
-                }
-                Err(e) => {
-                    return Err(e.into());
+        Ok(report)
+    }
+
+    /// Remove everything the bot created: the pool, every `botpy-` container and image,
+    /// and the sandbox directories. Called on shutdown, after the running jobs are drained.
+    pub async fn shutdown(&self) -> RemoveReport {
+        let closed = self.pool.close().await;
+
+        let mut report = RemoveReport::default();
+        for result in [self.rm_all_containers().await, self.rm_all_images().await] {
+            match result {
+                Ok(r) => {
+                    report.removed += r.removed;
+                    report.failed.extend(r.failed);
                 }
-                _ => {}
+                Err(e) => report.failed.push(("*".to_owned(), e.to_string())),
             }
         }
+        report.removed += closed;
 
-        let time = timer.elapsed();
+        match self.rm_sandbox_dirs().await {
+            Ok(removed) => report.removed += removed,
+            Err(e) => report
+                .failed
+                .push((self.sandbox_dir.clone(), e.to_string())),
+        }
 
-        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+        report
+    }
 
-        // stop and remove container
+    /// Remove what a previous instance left behind: containers and images labelled
+    /// with another instance id, and the sandbox directories.
+    /// Assumes one bot per docker daemon and `SANDBOX_DIR`.
+    async fn reap(&self) -> RemoveReport {
+        let mut report = RemoveReport::default();
+        let filters = HashMap::from([("label", vec![labels::INSTANCE])]);
+        let orphaned = |object_labels: Option<&HashMap<String, String>>| {
+            object_labels
+                .and_then(|object_labels| object_labels.get(labels::INSTANCE))
+                .is_some_and(|instance| *instance != self.instance)
+        };
 
-        self.docker
-            .stop_container(
-                &container_id,
-                Some(bollard::container::StopContainerOptions { t: 1 }),
-            )
+        match self
+            .docker
+            .list_containers(Some(bollard::container::ListContainersOptions::<&str> {
+                all: true,
+                filters: filters.clone(),
+                ..Default::default()
+            }))
             .await
-            .unwrap();
+        {
+            Ok(containers) => {
+                for container in containers {
+                    let Some(id) = container.id.filter(|_| orphaned(container.labels.as_ref()))
+                    else {
+                        continue;
+                    };
+
+                    match self.rm_container(&id).await {
+                        Ok(()) => report.removed += 1,
+                        Err(e) => report.failed.push((id, e.to_string())),
+                    }
+                }
+            }
+            Err(e) => report.failed.push(("containers".to_owned(), e.to_string())),
+        }
 
-        self.docker
-            .remove_container(
-                &container_id,
-                Some(bollard::container::RemoveContainerOptions {
-                    force: true,
-                    ..Default::default()
-                }),
-            )
+        match self
+            .docker
+            .list_images(Some(bollard::image::ListImagesOptions::<&str> {
+                filters,
+                ..Default::default()
+            }))
             .await
-            .unwrap();
+        {
+            Ok(images) => {
+                for image in images {
+                    if !orphaned(Some(&image.labels)) {
+                        continue;
+                    }
 
-        Ok(RunResult {
-            std_output,
-            std_error,
-            time,
-        })
+                    match self.rm_images(&image.id).await {
+                        Ok(()) => report.removed += 1,
+                        Err(e) => report.failed.push((image.id, e.to_string())),
+                    }
+                }
+            }
+            Err(e) => report.failed.push(("images".to_owned(), e.to_string())),
+        }
+
+        match self.rm_sandbox_dirs().await {
+            Ok(removed) => report.removed += removed,
+            Err(e) => report
+                .failed
+                .push((self.sandbox_dir.clone(), e.to_string())),
+        }
+
+        report
     }
 
+    /// Remove the sandbox directories `{SANDBOX_DIR}/{language}-{run id}` left
+    /// behind, of any language: the image of a language used by an earlier start
+    /// may not be available now. Returns how many were removed.
+    async fn rm_sandbox_dirs(&self) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+        let mut removed = 0;
+        let mut entries = tokio::fs::read_dir(&self.sandbox_dir).await?;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+
+            let owned = is_sandbox_dir(&name);
+
+            if owned && entry.file_type().await?.is_dir() {
+                tokio::fs::remove_dir_all(entry.path()).await?;
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Force-remove the container. A container already gone is not an error.
     pub async fn rm_container(
         &self,
         id: impl AsRef<str>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        self.docker
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let result = self
+            .docker
             .remove_container(
                 id.as_ref(),
                 Some(bollard::container::RemoveContainerOptions {
@@ -494,14 +1177,60 @@ impl DockerManager {
                     ..Default::default()
                 }),
             )
-            .await?;
+            .await;
 
-        Ok(())
+        match result {
+            Err(bollard::errors::Error::DockerResponseServerError {
+                status_code: 404, ..
+            }) => Ok(()),
+            result => Ok(result?),
+        }
     }
 
-    pub async fn rm_images(&self, id: impl AsRef<str>) -> Result<(), Box<dyn std::error::Error>> {
+    pub async fn rm_images(
+        &self,
+        id: impl AsRef<str>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         self.docker.remove_image(id.as_ref(), None, None).await?;
 
         Ok(())
     }
 }
+
+/// Append as much of `bytes` as fits in `limit` bytes.
+/// Whether the name is of a sandbox directory, `{language}-{run id}`.
+fn is_sandbox_dir(name: &str) -> bool {
+    // `-` and a hyphenated uuid
+    let Some(split) = name.len().checked_sub(37).filter(|split| *split > 0) else {
+        return false;
+    };
+
+    name.get(split..)
+        .and_then(|rest| rest.strip_prefix('-'))
+        .is_some_and(|run_id| uuid::Uuid::parse_str(run_id).is_ok())
+}
+
+fn push_capped(buf: &mut Vec<u8>, bytes: &[u8], limit: usize) {
+    let room = limit.saturating_sub(buf.len());
+    buf.extend_from_slice(&bytes[..bytes.len().min(room)]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sandbox_dir_names() {
+        assert!(is_sandbox_dir(
+            "python-01928f6e-7b1c-7d3a-9c4e-5f2a1b3c4d5e"
+        ));
+        // a language no longer in the registry
+        assert!(is_sandbox_dir(
+            "c-sharp-01928f6e-7b1c-7d3a-9c4e-5f2a1b3c4d5e"
+        ));
+
+        assert!(!is_sandbox_dir("-01928f6e-7b1c-7d3a-9c4e-5f2a1b3c4d5e"));
+        assert!(!is_sandbox_dir("python-latest"));
+        assert!(!is_sandbox_dir("notes"));
+    }
+}
@@ -0,0 +1,38 @@
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+/// Cancels a run from another task. Clones cancel the same run.
+#[derive(Clone)]
+pub struct CancelToken {
+    cancelled: Arc<watch::Sender<bool>>,
+}
+
+impl Default for CancelToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self {
+            cancelled: Arc::new(watch::Sender::new(false)),
+        }
+    }
+
+    /// Kill the running container of the run. The run ends with `Termination::Cancelled`.
+    pub fn cancel(&self) {
+        self.cancelled.send_replace(true);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.cancelled.borrow()
+    }
+
+    /// Wait until the run is cancelled.
+    pub async fn cancelled(&self) {
+        // the sender lives in self, so this never fails
+        let _ = self.cancelled.subscribe().wait_for(|c| *c).await;
+    }
+}
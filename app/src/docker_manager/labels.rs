@@ -0,0 +1,39 @@
+use std::collections::HashMap;
+
+/// Id of the bot process that created the object.
+pub const INSTANCE: &str = "botpy.instance";
+/// Id of the run (sandbox) the container belongs to.
+pub const RUN: &str = "botpy.run";
+/// traQ user id of the requester.
+pub const USER: &str = "botpy.user";
+/// traQ channel id the run was requested in.
+pub const CHANNEL: &str = "botpy.channel";
+
+/// Who asked for a run. Recorded on the containers of the run.
+#[derive(Debug, Clone, Default)]
+pub struct RunOwner {
+    pub user_id: String,
+    pub channel_id: String,
+}
+
+/// Labels of an object created by the instance.
+///
+/// `run` is left out of images, which outlive runs. `owner` is left out when not
+/// known, e.g. the owner of a pooled container is decided after it is created.
+pub(super) fn labels<'a>(
+    instance: &'a str,
+    run: Option<&'a str>,
+    owner: Option<&'a RunOwner>,
+) -> HashMap<&'a str, &'a str> {
+    let mut labels = HashMap::from([(INSTANCE, instance)]);
+
+    if let Some(run) = run {
+        labels.insert(RUN, run);
+    }
+    if let Some(owner) = owner {
+        labels.insert(USER, owner.user_id.as_str());
+        labels.insert(CHANNEL, owner.channel_id.as_str());
+    }
+
+    labels
+}
@@ -0,0 +1,193 @@
+/// How to run a snippet of one language.
+///
+/// The snippet is written to `/sandbox/{source_file}` and the commands run in
+/// `image` with the sandbox directory mounted on `/sandbox`. The program runs
+/// in `/tmp` with `/sandbox` and the root filesystem read-only.
+#[derive(Debug, Clone)]
+pub struct Language {
+    pub name: String,
+    /// Other fence tags selecting the language, e.g. `py` for python.
+    pub aliases: Vec<String>,
+    pub image: String,
+    pub source_file: String,
+    /// Command building the program, for compiled languages.
+    pub compile: Option<Vec<String>>,
+    /// Command running the program. Arguments given in the message are appended.
+    pub run: Vec<String>,
+    /// Environment variables of both commands, `KEY=VALUE`.
+    pub env: Vec<String>,
+}
+
+impl Language {
+    pub fn new(
+        name: impl Into<String>,
+        image: impl Into<String>,
+        source_file: impl Into<String>,
+        run: &[&str],
+    ) -> Self {
+        Self {
+            name: name.into(),
+            aliases: Vec::new(),
+            image: image.into(),
+            source_file: source_file.into(),
+            compile: None,
+            run: run.iter().map(|s| s.to_string()).collect(),
+            env: Vec::new(),
+        }
+    }
+
+    pub fn aliases(mut self, aliases: &[&str]) -> Self {
+        self.aliases = aliases.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    pub fn compile(mut self, compile: &[&str]) -> Self {
+        self.compile = Some(compile.iter().map(|s| s.to_string()).collect());
+        self
+    }
+
+    pub fn env(mut self, env: &[&str]) -> Self {
+        self.env = env.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    fn matches(&self, tag: &str) -> bool {
+        self.name.eq_ignore_ascii_case(tag)
+            || self.aliases.iter().any(|a| a.eq_ignore_ascii_case(tag))
+    }
+}
+
+/// Languages the bot can run, looked up by fence tag.
+#[derive(Debug, Clone)]
+pub struct LanguageRegistry {
+    languages: Vec<Language>,
+}
+
+impl LanguageRegistry {
+    pub fn new() -> Self {
+        Self {
+            languages: Vec::new(),
+        }
+    }
+
+    /// Register a language. Replaces the language of the same name.
+    pub fn add(&mut self, language: Language) {
+        self.languages.retain(|l| l.name != language.name);
+        self.languages.push(language);
+    }
+
+    /// Find the language by its name or one of its aliases.
+    pub fn get(&self, tag: impl AsRef<str>) -> Option<&Language> {
+        self.languages.iter().find(|l| l.matches(tag.as_ref()))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Language> {
+        self.languages.iter()
+    }
+
+    /// The built-in languages. The image of each language can be overridden
+    /// with `{NAME}_IMAGE`, e.g. `PYTHON_IMAGE` or `CPP_IMAGE`.
+    pub fn from_env() -> Self {
+        let mut registry = Self::default();
+
+        for language in &mut registry.languages {
+            let key = format!("{}_IMAGE", language.name.to_ascii_uppercase());
+            if let Ok(image) = std::env::var(key) {
+                language.image = image;
+            }
+        }
+
+        registry
+    }
+}
+
+impl Default for LanguageRegistry {
+    fn default() -> Self {
+        let mut registry = Self::new();
+
+        registry.add(
+            Language::new(
+                "python",
+                "python:latest",
+                "main.py",
+                &["python3", "/sandbox/main.py"],
+            )
+            .aliases(&["py", "python3"])
+            // keep stdout and stderr in the order they are written
+            .env(&["PYTHONUNBUFFERED=1"]),
+        );
+        registry.add(
+            Language::new(
+                "javascript",
+                "node:latest",
+                "main.js",
+                &["node", "/sandbox/main.js"],
+            )
+            .aliases(&["js", "node"]),
+        );
+        registry.add(
+            Language::new(
+                "ruby",
+                "ruby:latest",
+                "main.rb",
+                &["ruby", "/sandbox/main.rb"],
+            )
+            .aliases(&["rb"]),
+        );
+        registry.add(
+            Language::new("c", "gcc:latest", "main.c", &["/sandbox/main"]).compile(&[
+                "gcc",
+                "-std=c17",
+                "-O2",
+                "-o",
+                "/sandbox/main",
+                "/sandbox/main.c",
+                "-lm",
+            ]),
+        );
+        registry.add(
+            Language::new("cpp", "gcc:latest", "main.cpp", &["/sandbox/main"])
+                .aliases(&["c++", "cxx"])
+                .compile(&[
+                    "g++",
+                    "-std=c++20",
+                    "-O2",
+                    "-o",
+                    "/sandbox/main",
+                    "/sandbox/main.cpp",
+                ]),
+        );
+        registry.add(
+            Language::new("rust", "rust:latest", "main.rs", &["/sandbox/main"])
+                .aliases(&["rs"])
+                .compile(&[
+                    "rustc",
+                    "--edition",
+                    "2021",
+                    "-O",
+                    "-o",
+                    "/sandbox/main",
+                    "/sandbox/main.rs",
+                ]),
+        );
+        registry.add(
+            Language::new("go", "golang:latest", "main.go", &["/sandbox/main"])
+                .aliases(&["golang"])
+                .compile(&["go", "build", "-o", "/sandbox/main", "/sandbox/main.go"])
+                // the build cache does not fit in the /tmp tmpfs.
+                // -p=1 keeps the build under the pids limit.
+                .env(&["GOCACHE=/sandbox/.cache/go-build", "GOFLAGS=-p=1"]),
+        );
+        registry.add(
+            Language::new(
+                "shell",
+                "bash:latest",
+                "main.sh",
+                &["bash", "/sandbox/main.sh"],
+            )
+            .aliases(&["sh", "bash"]),
+        );
+
+        registry
+    }
+}
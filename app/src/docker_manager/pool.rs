@@ -0,0 +1,334 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use bollard::{container, Docker};
+
+use super::{labels, Language, ResourceLimits, RunOwner};
+
+/// A sandbox directory and the containers mounting it, created but not started.
+pub(super) struct Sandbox {
+    /// Run id, also labelled on the containers.
+    pub id: String,
+    pub dir: String,
+    /// Container running the compile command, for compiled languages.
+    pub compile: Option<String>,
+    /// Container running the program.
+    pub run: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PoolStats {
+    /// Runs served by a pre-created sandbox.
+    pub hits: u64,
+    /// Runs that had to create their sandbox.
+    pub misses: u64,
+    /// Idle sandboxes per language.
+    pub idle: HashMap<String, usize>,
+}
+
+/// Pre-created sandboxes per language, so a run does not wait for `create_container`.
+///
+/// Pooled containers are created before their owner is known, so they carry the
+/// instance and run labels only. The owner of a run is logged with its run id.
+///
+/// A sandbox is used for one run only. It is destroyed afterwards and the pool
+/// is topped up in the background.
+pub(super) struct SandboxPool {
+    docker: Docker,
+    instance: String,
+    limits: ResourceLimits,
+    sandbox_dir: String,
+    /// Idle sandboxes kept per language. 0 disables the pool.
+    size: usize,
+    state: Mutex<PoolState>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Default)]
+struct PoolState {
+    /// Set by `close`. Sandboxes are no longer kept idle.
+    closed: bool,
+    idle: HashMap<String, Vec<Sandbox>>,
+    /// Sandboxes being created per language, counted towards `size`.
+    pending: HashMap<String, usize>,
+}
+
+impl SandboxPool {
+    pub fn new(
+        docker: Docker,
+        instance: String,
+        limits: ResourceLimits,
+        sandbox_dir: String,
+        size: usize,
+    ) -> Self {
+        Self {
+            docker,
+            instance,
+            limits,
+            sandbox_dir,
+            size,
+            state: Mutex::new(PoolState::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Take a sandbox for the language, creating one if none is idle.
+    ///
+    /// The run command is fixed when the container is created, so runs with
+    /// arguments always get a fresh sandbox. Only fresh sandboxes are labelled
+    /// with the owner.
+    pub async fn acquire(
+        self: &Arc<Self>,
+        language: &Language,
+        args: &[String],
+        owner: &RunOwner,
+    ) -> Result<Sandbox, Box<dyn std::error::Error + Send + Sync>> {
+        if args.is_empty() {
+            let sandbox = self
+                .state
+                .lock()
+                .unwrap()
+                .idle
+                .get_mut(&language.name)
+                .and_then(|idle| idle.pop());
+
+            self.replenish(language);
+
+            if let Some(sandbox) = sandbox {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(sandbox);
+            }
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        self.create(language, args, Some(owner)).await
+    }
+
+    /// Create sandboxes in the background until the language has `size` of them.
+    pub fn replenish(self: &Arc<Self>, language: &Language) {
+        let missing = {
+            let mut state = self.state.lock().unwrap();
+            if state.closed {
+                return;
+            }
+            let idle = state.idle.get(&language.name).map_or(0, |idle| idle.len());
+            let pending = state.pending.entry(language.name.clone()).or_default();
+            let missing = self.size.saturating_sub(idle + *pending);
+            *pending += missing;
+            missing
+        };
+
+        for _ in 0..missing {
+            let pool = self.clone();
+            let language = language.clone();
+
+            tokio::spawn(async move {
+                let sandbox = pool.create(&language, &[], None).await;
+
+                let sandbox = {
+                    let mut state = pool.state.lock().unwrap();
+                    *state.pending.entry(language.name.clone()).or_default() -= 1;
+
+                    match sandbox {
+                        Ok(sandbox) if state.closed => sandbox,
+                        Ok(sandbox) => {
+                            state.idle.entry(language.name).or_default().push(sandbox);
+                            return;
+                        }
+                        Err(e) => {
+                            println!("failed to create sandbox for {}: {}", language.name, e);
+                            return;
+                        }
+                    }
+                };
+
+                // created after the pool was closed
+                if let Err(e) = pool.remove(sandbox).await {
+                    println!("failed to remove sandbox: {}", e);
+                }
+            });
+        }
+    }
+
+    /// Remove the containers and the directory of a used sandbox in the background.
+    pub fn destroy(self: &Arc<Self>, sandbox: Sandbox) {
+        let pool = self.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = pool.remove(sandbox).await {
+                println!("failed to remove sandbox: {}", e);
+            }
+        });
+    }
+
+    /// Stop keeping sandboxes idle. Sandboxes being created are removed once created.
+    /// `acquire` still creates sandboxes on demand.
+    pub async fn close(&self) -> usize {
+        self.state.lock().unwrap().closed = true;
+        self.clear().await
+    }
+
+    /// Remove every idle sandbox. Returns how many were removed.
+    pub async fn clear(&self) -> usize {
+        let idle = std::mem::take(&mut self.state.lock().unwrap().idle);
+
+        let mut removed = 0;
+        for sandbox in idle.into_values().flatten() {
+            match self.remove(sandbox).await {
+                Ok(()) => removed += 1,
+                Err(e) => println!("failed to remove sandbox: {}", e),
+            }
+        }
+        removed
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        let state = self.state.lock().unwrap();
+
+        PoolStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            idle: state
+                .idle
+                .iter()
+                .map(|(name, idle)| (name.clone(), idle.len()))
+                .collect(),
+        }
+    }
+
+    /// Create the sandbox directory and its containers.
+    async fn create(
+        &self,
+        language: &Language,
+        args: &[String],
+        owner: Option<&RunOwner>,
+    ) -> Result<Sandbox, Box<dyn std::error::Error + Send + Sync>> {
+        let id = uuid::Uuid::now_v7().to_string();
+        let dir = format!("{}/{}-{}", self.sandbox_dir, language.name, id);
+        tokio::fs::create_dir_all(&dir).await?;
+
+        let labels = labels::labels(&self.instance, Some(&id), owner);
+        let mut run = language.run.clone();
+        run.extend(args.iter().cloned());
+
+        let mut compile = None;
+        let containers = async {
+            if let Some(cmd) = &language.compile {
+                compile = Some(
+                    self.create_container(language, "compile", cmd, &dir, &labels)
+                        .await?,
+                );
+            }
+            self.create_container(language, "run", &run, &dir, &labels)
+                .await
+        }
+        .await;
+
+        match containers {
+            Ok(run) => Ok(Sandbox {
+                id,
+                dir,
+                compile,
+                run,
+            }),
+            Err(e) => {
+                if let Some(id) = compile {
+                    let _ = self
+                        .docker
+                        .remove_container(
+                            &id,
+                            Some(bollard::container::RemoveContainerOptions {
+                                force: true,
+                                ..Default::default()
+                            }),
+                        )
+                        .await;
+                }
+                let _ = tokio::fs::remove_dir_all(&dir).await;
+                Err(e)
+            }
+        }
+    }
+
+    async fn create_container(
+        &self,
+        language: &Language,
+        phase: &str,
+        cmd: &[String],
+        dir: &str,
+        labels: &HashMap<&str, &str>,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let container_name = format!("botpy-{}-{}-{}", language.name, phase, uuid::Uuid::now_v7());
+
+        // the program may only write to the size-limited /tmp tmpfs. the
+        // compiler, which runs no code of the snippet, writes the program to
+        // /sandbox.
+        let run = phase == "run";
+        let mode = if run { "ro" } else { "rw" };
+
+        let container_config = container::Config {
+            image: Some(language.image.as_str()),
+            cmd: Some(cmd.iter().map(|s| s.as_str()).collect()),
+            env: Some(language.env.iter().map(|s| s.as_str()).collect()),
+            tty: Some(false),
+            // stdin is always attached and closed after the input is written
+            open_stdin: Some(true),
+            stdin_once: Some(true),
+            attach_stdin: Some(true),
+            attach_stdout: Some(true),
+            attach_stderr: Some(true),
+            network_disabled: Some(true),
+            labels: Some(labels.clone()),
+            working_dir: run.then_some("/tmp"),
+            host_config: Some(bollard::models::HostConfig {
+                binds: Some(vec![format!("{}:/sandbox:{}", dir, mode)]),
+                readonly_rootfs: Some(run),
+                init: Some(true),
+                ..self.limits.host_config()
+            }),
+            ..Default::default()
+        };
+
+        let container = self
+            .docker
+            .create_container::<&str, &str>(
+                Some(bollard::container::CreateContainerOptions::<&str> {
+                    name: container_name.as_str(),
+                    ..Default::default()
+                }),
+                container_config,
+            )
+            .await?;
+
+        Ok(container.id)
+    }
+
+    /// Remove the containers and the directory of a sandbox.
+    async fn remove(
+        &self,
+        sandbox: Sandbox,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        for id in sandbox.compile.into_iter().chain([sandbox.run]) {
+            self.docker
+                .remove_container(
+                    &id,
+                    Some(bollard::container::RemoveContainerOptions {
+                        force: true,
+                        ..Default::default()
+                    }),
+                )
+                .await?;
+        }
+
+        tokio::fs::remove_dir_all(&sandbox.dir).await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+use std::{collections::HashMap, time::Duration};
+
+use bollard::models::{HostConfig, ResourcesUlimits};
+
+use crate::{env::env_or, err::ServerError};
+
+const MIB: i64 = 1024 * 1024;
+
+/// Resource profile applied to every sandbox container.
+#[derive(Debug, Clone)]
+pub struct ResourceLimits {
+    /// Memory limit in bytes.
+    pub memory: i64,
+    /// Memory + swap limit in bytes. The same value as `memory` disables swap.
+    pub memory_swap: i64,
+    /// CPU quota in units of 10^-9 CPUs.
+    pub nano_cpus: i64,
+    /// Max number of processes / threads in the container.
+    pub pids_limit: i64,
+    /// Max number of open file descriptors.
+    pub nofile: i64,
+    /// Max size of a single file written by the program, in bytes.
+    pub fsize: i64,
+    /// Size of the tmpfs mounted on `/tmp`, in bytes. The only place a sandboxed
+    /// program may write, so this bounds the disk it can use.
+    pub tmpfs_size: i64,
+    /// Wall-clock deadline of a run. The container is killed when it is exceeded.
+    pub time_limit: Duration,
+    /// Wall-clock deadline of the compile step of compiled languages.
+    pub compile_time_limit: Duration,
+    /// Bytes of stdout and of stderr kept from a run. The rest is read and dropped.
+    pub output_limit: usize,
+}
+
+impl Default for ResourceLimits {
+    fn default() -> Self {
+        Self {
+            memory: 256 * MIB,
+            memory_swap: 256 * MIB,
+            nano_cpus: 1_000_000_000,
+            pids_limit: 64,
+            nofile: 256,
+            fsize: 16 * MIB,
+            tmpfs_size: 16 * MIB,
+            time_limit: Duration::from_secs(10),
+            compile_time_limit: Duration::from_secs(30),
+            output_limit: 64 * 1024,
+        }
+    }
+}
+
+impl ResourceLimits {
+    /// Load the limits from the environment, falling back to the defaults.
+    ///
+    /// - `SANDBOX_MEMORY_MB`
+    /// - `SANDBOX_MEMORY_SWAP_MB` (defaults to `SANDBOX_MEMORY_MB`, i.e. no swap)
+    /// - `SANDBOX_CPUS` (may be fractional, e.g. `0.5`)
+    /// - `SANDBOX_PIDS_LIMIT`
+    /// - `SANDBOX_NOFILE`
+    /// - `SANDBOX_FSIZE_MB`
+    /// - `SANDBOX_TMPFS_MB`
+    /// - `SANDBOX_TIME_LIMIT_SECS` (may be fractional)
+    /// - `SANDBOX_COMPILE_TIME_LIMIT_SECS` (may be fractional)
+    /// - `SANDBOX_OUTPUT_LIMIT_KB`
+    pub fn from_env() -> Result<Self, ServerError> {
+        let default = Self::default();
+
+        let memory = env_or("SANDBOX_MEMORY_MB", default.memory / MIB)? * MIB;
+        let memory_swap = env_or("SANDBOX_MEMORY_SWAP_MB", memory / MIB)? * MIB;
+        let cpus = env_or("SANDBOX_CPUS", default.nano_cpus as f64 / 1e9)?;
+
+        Ok(Self {
+            memory,
+            memory_swap,
+            nano_cpus: (cpus * 1e9) as i64,
+            pids_limit: env_or("SANDBOX_PIDS_LIMIT", default.pids_limit)?,
+            nofile: env_or("SANDBOX_NOFILE", default.nofile)?,
+            fsize: env_or("SANDBOX_FSIZE_MB", default.fsize / MIB)? * MIB,
+            tmpfs_size: env_or("SANDBOX_TMPFS_MB", default.tmpfs_size / MIB)? * MIB,
+            time_limit: secs_from_env("SANDBOX_TIME_LIMIT_SECS", default.time_limit)?,
+            compile_time_limit: secs_from_env(
+                "SANDBOX_COMPILE_TIME_LIMIT_SECS",
+                default.compile_time_limit,
+            )?,
+            output_limit: env_or("SANDBOX_OUTPUT_LIMIT_KB", default.output_limit / 1024)? * 1024,
+        })
+    }
+
+    /// Host config with the limits set. Other fields are left default.
+    /// The time limits are enforced by the manager, not by docker.
+    pub fn host_config(&self) -> HostConfig {
+        HostConfig {
+            memory: Some(self.memory),
+            memory_swap: Some(self.memory_swap),
+            nano_cpus: Some(self.nano_cpus),
+            pids_limit: Some(self.pids_limit),
+            ulimits: Some(vec![
+                ulimit("nofile", self.nofile),
+                ulimit("fsize", self.fsize),
+            ]),
+            tmpfs: Some(HashMap::from([(
+                "/tmp".to_string(),
+                format!("rw,nosuid,nodev,size={}", self.tmpfs_size),
+            )])),
+            // /dev/shm is writable too
+            shm_size: Some(self.tmpfs_size),
+            ..Default::default()
+        }
+    }
+}
+
+/// A duration in seconds, possibly fractional. Negative, NaN and infinite
+/// values are invalid.
+fn secs_from_env(key: &str, default: Duration) -> Result<Duration, ServerError> {
+    let secs = env_or(key, default.as_secs_f64())?;
+
+    Duration::try_from_secs_f64(secs).map_err(|e| ServerError::InvalidEnv {
+        key: key.to_string(),
+        source: Box::new(e),
+    })
+}
+
+fn ulimit(name: &str, limit: i64) -> ResourcesUlimits {
+    ResourcesUlimits {
+        name: Some(name.to_string()),
+        soft: Some(limit),
+        hard: Some(limit),
+    }
+}
+
+/// The limit a run ran into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitExceeded {
+    /// The program was OOM-killed.
+    Memory { limit: i64 },
+    /// The program reached the process / thread limit. Forking may have failed
+    /// because of it, or the program only used that many.
+    Pids { limit: i64 },
+}
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LimitExceeded::Memory { limit } => {
+                write!(f, "memory limit exceeded ({} MiB, OOM-killed)", limit / MIB)
+            }
+            LimitExceeded::Pids { limit } => {
+                write!(
+                    f,
+                    "reached the process limit ({} processes). creating processes or threads may have failed",
+                    limit
+                )
+            }
+        }
+    }
+}
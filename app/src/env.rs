@@ -0,0 +1,40 @@
+use crate::err::ServerError;
+
+pub fn env(key: &str) -> Result<String, ServerError> {
+    std::env::var(key).map_err(|e| ServerError::EnvNotFound(format!("{}: {}", key, e).into()))
+}
+
+/// Parse the env var, or `default` if it is not set.
+pub fn env_or<T>(key: &str, default: T) -> Result<T, ServerError>
+where
+    T: std::str::FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match std::env::var(key) {
+        Ok(value) => parse(key, &value),
+        Err(_) => Ok(default),
+    }
+}
+
+/// Like `env_or`, for a count that must not be 0.
+pub fn env_nonzero(key: &str, default: usize) -> Result<usize, ServerError> {
+    match env_or(key, default)? {
+        0 => Err(ServerError::InvalidEnv {
+            key: key.to_string(),
+            source: "must be greater than 0".into(),
+        }),
+        value => Ok(value),
+    }
+}
+
+/// Parse the value of the env var.
+pub fn parse<T>(key: &str, value: &str) -> Result<T, ServerError>
+where
+    T: std::str::FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    value.parse().map_err(|e: T::Err| ServerError::InvalidEnv {
+        key: key.to_string(),
+        source: Box::new(e),
+    })
+}
@@ -1,22 +1,31 @@
 use std::{error::Error, fmt::Display};
 
 #[derive(Debug)]
-pub enum ServerError{
-    EnvNotFound(Box<dyn Error>),
-
+pub enum ServerError {
+    EnvNotFound(Box<dyn Error + Send + Sync>),
+    InvalidEnv {
+        key: String,
+        source: Box<dyn Error + Send + Sync>,
+    },
 }
 
 impl Display for ServerError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             ServerError::EnvNotFound(e) => write!(f, "Environment variable not found: {}", e),
+            ServerError::InvalidEnv { key, source } => {
+                write!(f, "Invalid environment variable {}: {}", key, source)
+            }
         }
     }
 }
 
 impl Error for ServerError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
-        None
+        match self {
+            ServerError::InvalidEnv { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
     }
 
     fn description(&self) -> &str {
@@ -26,4 +35,4 @@ impl Error for ServerError {
     fn cause(&self) -> Option<&dyn Error> {
         self.source()
     }
-}
\ No newline at end of file
+}
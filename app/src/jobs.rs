@@ -0,0 +1,57 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::docker_manager::CancelToken;
+
+/// The job run for each request message, so it can be cancelled when the
+/// message is edited or deleted.
+#[derive(Default)]
+pub struct Jobs {
+    jobs: Mutex<HashMap<String, Job>>,
+}
+
+struct Job {
+    job_id: String,
+    cancel: CancelToken,
+}
+
+impl Jobs {
+    /// Record the job of the request.
+    pub fn start(&self, request_id: &str, job_id: &str, cancel: CancelToken) {
+        self.jobs.lock().unwrap().insert(
+            request_id.to_string(),
+            Job {
+                job_id: job_id.to_string(),
+                cancel,
+            },
+        );
+    }
+
+    /// Forget the job once it finished, unless the request started another one.
+    /// Returns false if the job was cancelled through `cancel` in the meantime.
+    pub fn finish(&self, request_id: &str, job_id: &str) -> bool {
+        let mut jobs = self.jobs.lock().unwrap();
+        if jobs.get(request_id).is_some_and(|job| job.job_id == job_id) {
+            jobs.remove(request_id);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Cancel the job of the request. Returns the scheduler job id to take
+    /// it out of the queue, if there was a job.
+    pub fn cancel(&self, request_id: &str) -> Option<String> {
+        let job = self.jobs.lock().unwrap().remove(request_id)?;
+        job.cancel.cancel();
+        Some(job.job_id)
+    }
+
+    /// Like `cancel`, but the job is still answered: it ends as cancelled
+    /// rather than being dropped.
+    pub fn kill(&self, request_id: &str) -> Option<String> {
+        let jobs = self.jobs.lock().unwrap();
+        let job = jobs.get(request_id)?;
+        job.cancel.cancel();
+        Some(job.job_id.clone())
+    }
+}
@@ -1,16 +1,30 @@
-use futures::{future::BoxFuture, FutureExt};
-use std::{error::Error, sync::Arc};
+use std::{borrow::Cow, error::Error, sync::Arc, time::Duration};
 
 use traq_python_bot::{
-    event::{Event, Message, MessageBody, MessageCreatedUpdated},
-    event_loop::EventLoop,
+    event::{BotMessageStampsUpdated, MessageCreatedUpdated, MessageDeleted},
+    event_loop::EventLoopBuilder,
+    router::{Context, Router},
     traq_api::TraqApi,
+    traq_server_connecter::{ReconnectPolicy, TraqServerConnecterBuilder},
 };
 
 mod docker_manager;
-use docker_manager::DockerManager;
+use docker_manager::{
+    CancelToken, CompileResult, DockerManager, LanguageRegistry, RemoveReport, ResourceLimits,
+    RunOwner, RunResult, Termination,
+};
+mod env;
+use env::{env, env_nonzero, env_or};
 mod err;
+mod jobs;
+use jobs::Jobs;
 mod parse;
+mod reactions;
+use reactions::{Outcome, Reactions, StampAction, StampActions, Stamps};
+mod replies;
+use replies::{Replies, Request};
+mod scheduler;
+use scheduler::Scheduler;
 
 #[tokio::main]
 async fn main() {
@@ -23,12 +37,9 @@ Server stopped with error:\n
             e
         );
     };
-
-    // remove all containers and images
-    todo!()
 }
 
-async fn server_main() -> Result<(), Box<dyn std::error::Error>> {
+async fn server_main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     println!("Starting server...");
 
     dotenvy::dotenv()?;
@@ -37,39 +48,43 @@ async fn server_main() -> Result<(), Box<dyn std::error::Error>> {
         println!("{}: {}", key, value);
     }
 
-    let host = std::env::var("TRAQ_HOST")?;
-    let bot_id = std::env::var("TRAQ_BOT_ID")?;
-    let token = std::env::var("TRAQ_BOT_TOKEN")?;
-    let sandbox_dir = std::env::var("SANDBOX_DIR")?;
-    let wait_dockerd_time = std::env::var("WAIT_DOCKERD_TIME")?;
-    let wait_dockerd_time = wait_dockerd_time.parse::<u64>()?;
+    let host = env("TRAQ_HOST")?;
+    let bot_id = env("TRAQ_BOT_ID")?;
+    let token = env("TRAQ_BOT_TOKEN")?;
+    let sandbox_dir = env("SANDBOX_DIR")?;
+    let wait_dockerd_time: u64 = env::parse("WAIT_DOCKERD_TIME", &env("WAIT_DOCKERD_TIME")?)?;
+    let resource_limits = ResourceLimits::from_env()?;
+    let pool_size = env_or("SANDBOX_POOL_SIZE", 2)?;
+    let max_running_jobs = env_nonzero("MAX_RUNNING_JOBS", 4)?;
+    let max_running_jobs_per_user = env_nonzero("MAX_RUNNING_JOBS_PER_USER", 1)?;
+    // how long running jobs may take to finish on shutdown
+    let shutdown_timeout = Duration::from_secs(env_or("SHUTDOWN_TIMEOUT_SECS", 60)?);
+    // events handled at a time
+    let max_handlers = env_nonzero("MAX_HANDLERS", 64)?;
+    // reconnect attempts in a row before giving up. 0 retries forever.
+    let max_attempts = env_or(
+        "RECONNECT_MAX_ATTEMPTS",
+        ReconnectPolicy::default().max_attempts.unwrap_or(0),
+    )?;
+    let reconnect = ReconnectPolicy {
+        max_attempts: Some(max_attempts).filter(|max| *max > 0),
+        ..Default::default()
+    };
+    // user names or ids allowed to run the admin commands, comma separated.
+    // names are resolved to ids below.
+    let admin_users = std::env::var("ADMIN_USERS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>();
 
     println!("env loaded.");
 
     // create parser
 
-    let mut parser = parse::Parser::new();
-    parser.add("ping", format!(r"(?s)^@{} +-ping$", regex::escape(&bot_id)));
-    parser.add(
-        "docker-hello",
-        format!(r"(?s)^@{} +-docker-hello$", regex::escape(&bot_id)),
-    );
-    parser.add(
-        "rm-all-containers",
-        format!(r"(?s)^@{} +-rm-all-containers$", regex::escape(&bot_id)),
-    );
-    parser.add(
-        "rm-all-images",
-        format!(r"(?s)^@{} +-rm-all-images$", regex::escape(&bot_id)),
-    );
-
-    parser.add(
-        "python",
-        format!(
-            r"(?s)^@{}(?<arg>.*)\n+```(?:python)?\n(?<code>.*?)\n```$",
-            regex::escape(&bot_id)
-        ),
-    );
+    let parser = command_parser(&bot_id, false);
+    let dm_parser = command_parser(&bot_id, true);
 
     println!("Parser created.");
 
@@ -102,151 +117,593 @@ async fn server_main() -> Result<(), Box<dyn std::error::Error>> {
         }
     };
 
-    let docker = docker_manager.build().await?;
+    let docker = Arc::new(
+        docker_manager
+            .resource_limits(resource_limits)
+            .languages(LanguageRegistry::from_env())
+            .pool_size(pool_size)
+            .build()
+            .await?,
+    );
+    let scheduler = Scheduler::new(max_running_jobs, max_running_jobs_per_user);
+
+    let api = TraqApi::new(&host, &token);
+    let admin_users = admin_ids(&api, admin_users).await;
+    // the stamps are cosmetic. the bot runs without them.
+    let stamps = Stamps::fetch(&api).await.unwrap_or_else(|e| {
+        println!("failed to fetch stamps: {}. running without stamps.", e);
+        Stamps::default()
+    });
+    // stamps showing the progress of code requests
+    let reactions = Reactions::from_env(&stamps);
+    // stamps acting on the request when put on a reply
+    let stamp_actions = StampActions::from_env(&stamps);
 
     let stats = Stats {
-        sandbox_dir,
         parser,
-        docker,
+        dm_parser,
+        replies: Replies::new(1024),
+        reactions,
+        stamp_actions,
+        jobs: Jobs::default(),
+        docker: docker.clone(),
+        scheduler: scheduler.clone(),
+        admin_users,
     };
 
     println!("Docker prepared.");
 
     // create event loop
 
-    let mut event_loop = EventLoop::build_from_host_and_token(host.to_string(), token).await;
-    println!("Start event loop.");
-    event_loop.run(stats, event_loop_fn).await;
-    Ok(())
+    let result = async {
+        let mut event_loop = EventLoopBuilder {
+            connecter: TraqServerConnecterBuilder {
+                host: host.to_string(),
+                bot_token: token,
+            },
+            reconnect,
+        }
+        .build()
+        .await?;
+        println!("Start event loop.");
+
+        let shutdown_handle = event_loop.shutdown_handle();
+        let router = Router::new()
+            .on_message_created(on_message_created)
+            .on_direct_message_created(on_direct_message_created)
+            .on_message_updated(on_message_updated)
+            .on_direct_message_updated(on_direct_message_updated)
+            .on_message_deleted(on_message_deleted)
+            .on_direct_message_deleted(on_direct_message_deleted)
+            .on_stamps_updated(on_stamps_updated);
+        let run = event_loop.run_router(stats, router, max_handlers);
+        tokio::pin!(run);
+
+        tokio::select! {
+            result = &mut run => {
+                result?;
+                Err("event loop stopped".into())
+            }
+            signal = shutdown_signal() => {
+                println!("Received {}.", signal?);
+
+                // queued jobs are answered as cancelled, running ones finish
+                scheduler.close();
+                shutdown_handle.shutdown();
+                if tokio::time::timeout(shutdown_timeout, run).await.is_err() {
+                    println!("handlers did not finish in {}s.", shutdown_timeout.as_secs());
+                }
+                Ok(())
+            }
+        }
+    }
+    .await;
+
+    shutdown(&scheduler, &docker, shutdown_timeout).await;
+
+    result
+}
+
+/// Resolve the user names among `users` to ids. Stamp events only carry the id.
+/// Names of no user, or that fail to resolve, are left out.
+async fn admin_ids(api: &TraqApi, users: Vec<String>) -> Vec<String> {
+    let mut ids = Vec::new();
+
+    for user in users {
+        if uuid::Uuid::parse_str(&user).is_ok() {
+            ids.push(user);
+            continue;
+        }
+
+        match api.find_user(&user).await {
+            Ok(Some(found)) => ids.push(found.id),
+            Ok(None) => println!("admin user not found: {}", user),
+            Err(e) => println!("failed to find admin user {}: {}", user, e),
+        }
+    }
+
+    ids
+}
+
+/// Wait for SIGINT or SIGTERM. Returns the name of the signal.
+async fn shutdown_signal() -> Result<&'static str, std::io::Error> {
+    let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result.map(|_| "SIGINT"),
+        _ = sigterm.recv() => Ok("SIGTERM"),
+    }
+}
+
+/// Stop taking jobs, let the running ones finish, then remove every docker object
+/// and sandbox directory the bot created.
+async fn shutdown(scheduler: &Scheduler, docker: &DockerManager, timeout: Duration) {
+    println!("Shutting down...");
+
+    let cancelled = scheduler.close();
+    println!("cancelled {} queued job(s).", cancelled);
+
+    if tokio::time::timeout(timeout, scheduler.drain())
+        .await
+        .is_err()
+    {
+        println!("running jobs did not finish in {}s.", timeout.as_secs());
+    }
+
+    let report = docker.shutdown().await;
+    println!("removed {} docker object(s) / sandbox(es).", report.removed);
+    for (id, e) in &report.failed {
+        println!("failed to remove {}: {}", id, e);
+    }
+}
+
+/// Parser of the bot commands. In channels a command starts with `@{bot_id}`,
+/// in direct messages the mention may be left out.
+fn command_parser(bot_id: &str, dm: bool) -> parse::Parser {
+    let mention = format!("@{}", regex::escape(bot_id));
+    // `@bot -ping`
+    let command = if dm {
+        format!(r"(?:{} +)?", mention)
+    } else {
+        format!(r"{} +", mention)
+    };
+    // `@bot {args}\n```...````
+    let code = if dm {
+        format!(r"(?:(?:{})?(?<arg>[^\n]*)\n+)?", mention)
+    } else {
+        format!(r"{}(?<arg>[^\n]*)\n+", mention)
+    };
+
+    let mut parser = parse::Parser::new();
+    for name in [
+        "ping",
+        "docker-hello",
+        "cancel",
+        "pool-stats",
+        "rm-all-containers",
+        "rm-all-images",
+    ] {
+        parser.add(name, format!(r"(?s)^{}-{}$", command, name));
+    }
+    parser.add("code", format!(r"(?s)^{}(?<blocks>```.*```)$", code));
+
+    parser
 }
 
 struct Stats {
-    sandbox_dir: String,
     parser: parse::Parser,
-    docker: DockerManager,
-}
-
-fn event_loop_fn(
-    message: tokio_tungstenite::tungstenite::Message,
-    api: TraqApi,
-    stats: Arc<Stats>,
-) -> BoxFuture<'static, ()> {
-    async move {
-        match message {
-            tokio_tungstenite::tungstenite::Message::Text(utf8_bytes) => {
-                let event = Event::from_json(&utf8_bytes.to_string()).unwrap();
-
-                if let Event::Message {
-                    body: Message::MessageCreated(MessageCreatedUpdated { message, .. }),
-                    ..
-                } = event
-                {
-                    let MessageBody { plain_text, .. } = message;
-
-                    println!("Received:\n{}", plain_text);
-
-                    let Some((pattern_name, captures)) = &stats.parser.parse(&plain_text) else {
-                        println!("Send:\n:question:");
-                        api.send_message(&message.channel_id, ":question:", false)
-                            .await
-                            .unwrap();
-                        return;
-                    };
-
-                    let response = match pattern_name.as_str() {
-                        "ping" => "pong".to_owned(),
-                        "docker-hello" => {
-                            let result = stats.docker.hello().await.unwrap();
-
-                            let mut output = format!(
-                                "time: {}ms\nstdout:\n```\n{}\n```",
-                                result.time.as_millis(),
-                                result.std_output
-                            );
-
-                            if !result.std_error.is_empty() {
-                                output.push_str(&format!(
-                                    "\nstderr:\n```\n{}\n```",
-                                    result.std_error
-                                ));
-                            }
-
-                            output
-                        }
-                        "rm-all-containers" => "not implemented.".to_owned(),
-                        "rm-all-images" => "not implemented.".to_owned(),
-                        "python" => {
-                            let code = captures["code"].to_string();
-                            let args = captures["arg"]
-                                .split_whitespace()
-                                .map(|s| s.to_string())
-                                .collect::<Vec<_>>();
-
-                            python(code, args, &stats.sandbox_dir, &stats.docker)
-                                .await
-                                .unwrap()
-                        }
-                        _ => {
-                            panic!(
-                                "Unknown pattern name: {} | event loop do not match all patterns.",
-                                pattern_name
-                            );
-                        }
-                    };
-
-                    println!("Send:\n{}", response);
-                    api.send_message(&message.channel_id, &response, false)
-                        .await
-                        .unwrap();
+    /// The parser of direct messages, where the mention is optional.
+    dm_parser: parse::Parser,
+    replies: Replies,
+    reactions: Reactions,
+    stamp_actions: StampActions,
+    jobs: Jobs,
+    docker: Arc<DockerManager>,
+    scheduler: Arc<Scheduler>,
+    /// user ids
+    admin_users: Vec<String>,
+}
+
+impl Stats {
+    fn is_admin(&self, user_id: &str) -> bool {
+        self.admin_users.iter().any(|admin| admin == user_id)
+    }
+}
+
+async fn on_message_created(event: MessageCreatedUpdated, cx: Context<Stats>) {
+    handle_message(event, false, cx).await
+}
+
+/// Run the edited message again, editing the previous reply.
+async fn on_message_updated(event: MessageCreatedUpdated, cx: Context<Stats>) {
+    handle_message(event, false, cx).await
+}
+
+async fn on_direct_message_updated(event: MessageCreatedUpdated, cx: Context<Stats>) {
+    handle_message(event, true, cx).await
+}
+
+async fn on_direct_message_created(event: MessageCreatedUpdated, cx: Context<Stats>) {
+    handle_message(event, true, cx).await
+}
+
+/// Run the command of the message and reply in its channel.
+async fn handle_message(event: MessageCreatedUpdated, dm: bool, cx: Context<Stats>) {
+    let Context {
+        api, state: stats, ..
+    } = cx;
+    let MessageCreatedUpdated { message, .. } = event;
+    let request = Request { message, dm };
+    let message = &request.message;
+    let plain_text = &message.plain_text;
+
+    println!("Received:\n{}", plain_text);
+
+    // the message was edited. drop the run of the old version, and its running
+    // stamp: the new version may not be a code run. a code run stamps it again.
+    if cancel_job(&stats, &message.id) {
+        stats.reactions.finish(&api, &message.id, None).await;
+    }
+
+    let parser = if dm { &stats.dm_parser } else { &stats.parser };
+
+    let Some((pattern_name, captures)) = &parser.parse(&plain_text) else {
+        reply(&stats, &api, &request, ":question:").await;
+        return;
+    };
+
+    let response = match pattern_name.as_str() {
+        "ping" => "pong".to_owned(),
+        "docker-hello" => match stats.docker.hello().await {
+            Ok(result) => {
+                let mut output = format!(
+                    "time: {}ms\nstdout:\n```\n{}\n```",
+                    result.time.as_millis(),
+                    result.std_output
+                );
+
+                if !result.std_error.is_empty() {
+                    output.push_str(&format!("\nstderr:\n```\n{}\n```", result.std_error));
+                }
+
+                output
+            }
+            Err(e) => format!(":x: {}", e),
+        },
+        "pool-stats" => {
+            let stats = stats.docker.pool_stats();
+
+            let mut idle = stats
+                .idle
+                .iter()
+                .map(|(name, idle)| format!("{}: {}", name, idle))
+                .collect::<Vec<_>>();
+            idle.sort();
+
+            format!(
+                "hits: {}\nmisses: {}\nidle: {}",
+                stats.hits,
+                stats.misses,
+                idle.join(", ")
+            )
+        }
+        "cancel" => {
+            let cancelled = stats.scheduler.cancel_user(&message.user.id);
+            format!("cancelled {} queued job(s).", cancelled)
+        }
+        "rm-all-containers" | "rm-all-images" if !stats.is_admin(&message.user.id) => {
+            ":no_entry_sign: admin only.".to_owned()
+        }
+        "rm-all-containers" => match stats.docker.rm_all_containers().await {
+            Ok(report) => remove_report("container", &report),
+            Err(e) => format!(":x: failed to list containers: {}", e),
+        },
+        "rm-all-images" => match stats.docker.rm_all_images().await {
+            Ok(report) => remove_report("image", &report),
+            Err(e) => format!(":x: failed to list images: {}", e),
+        },
+        "code" => {
+            // e.g. a fence without a newline after the tag
+            let Some(snippet) = parse::Snippet::parse(&captures["blocks"]) else {
+                reply(&stats, &api, &request, ":question:").await;
+                return;
+            };
+            let args = captures
+                .name("arg")
+                .map_or("", |arg| arg.as_str())
+                .split_whitespace()
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>();
+
+            let owner = RunOwner {
+                user_id: message.user.id.clone(),
+                channel_id: message.channel_id.clone(),
+            };
+
+            let cancel = CancelToken::new();
+            let ticket = stats.scheduler.submit(&message.user.id);
+            let job_id = ticket.job_id().to_string();
+            stats.jobs.start(&message.id, &job_id, cancel.clone());
+            stats.reactions.start(&api, &message.id).await;
+
+            if let Some(position) = ticket.position() {
+                let response = format!(":hourglass: queued, {} ahead of you", position);
+                reply(&stats, &api, &request, &response).await;
+            }
+
+            let (response, outcome) = match ticket.wait().await {
+                Ok(_permit) => {
+                    match run_code(snippet, args, &owner, &cancel, &stats.docker).await {
+                        Ok((response, outcome)) => (response, Some(outcome)),
+                        Err(e) => (format!(":x: failed to run: {}", e), Some(Outcome::Failure)),
+                    }
                 }
+                Err(e) => (format!(":no_entry_sign: {}", e), None),
+            };
+
+            // the message was edited again or deleted. the stamps are up to the
+            // run of the edited message.
+            if !stats.jobs.finish(&message.id, &job_id) {
+                return;
+            }
+
+            stats.reactions.finish(&api, &message.id, outcome).await;
+
+            response
+        }
+        _ => {
+            panic!(
+                "Unknown pattern name: {} | event loop do not match all patterns.",
+                pattern_name
+            );
+        }
+    };
+
+    reply(&stats, &api, &request, &response).await;
+}
+
+/// Cancel the job of the deleted message and delete the reply.
+async fn on_message_deleted(event: MessageDeleted, cx: Context<Stats>) {
+    handle_deleted(event, cx).await
+}
+
+async fn on_direct_message_deleted(event: MessageDeleted, cx: Context<Stats>) {
+    handle_deleted(event, cx).await
+}
+
+async fn handle_deleted(event: MessageDeleted, cx: Context<Stats>) {
+    let Context {
+        api, state: stats, ..
+    } = cx;
+    let request_id = &event.message.id;
+
+    cancel_job(&stats, request_id);
+
+    if let Some(reply_id) = stats.replies.remove(request_id) {
+        println!("Delete: {}", reply_id);
+        if let Err(e) = api.delete_message(&reply_id).await {
+            println!("failed to delete reply: {}", e);
+        }
+    }
+}
+
+/// Act on the stamps newly put on a reply of the bot. Only the author of the
+/// request and the admins may.
+async fn on_stamps_updated(event: BotMessageStampsUpdated, cx: Context<Stats>) {
+    let stats = &cx.state;
+    let reply_id = &event.message_id;
+
+    let Some((request, added)) = stats.replies.stamped(reply_id, &event.stamps) else {
+        return;
+    };
+
+    let actions = added
+        .into_iter()
+        .filter_map(|stamp| Some((stats.stamp_actions.get(&stamp.stamp_id)?, stamp)))
+        .filter(|(action, stamp)| {
+            let allowed =
+                stamp.user_id == request.message.user.id || stats.is_admin(&stamp.user_id);
+            if !allowed {
+                println!("{:?} by {}: not allowed", action, stamp.user_id);
             }
-            tokio_tungstenite::tungstenite::Message::Close(close_frame) => todo!(),
-            _ => (),
+            allowed
+        })
+        .map(|(action, _)| action)
+        .collect::<Vec<_>>();
+
+    // the stamps of one event act once
+    let request_id = &request.message.id;
+    if actions.contains(&StampAction::Delete) {
+        // the job would only post a new reply
+        if cancel_job(stats, request_id) {
+            stats.reactions.finish(&cx.api, request_id, None).await;
         }
+
+        if let Some(reply_id) = stats.replies.remove(request_id) {
+            println!("Delete: {}", reply_id);
+            if let Err(e) = cx.api.delete_message(&reply_id).await {
+                println!("failed to delete reply: {}", e);
+            }
+        }
+    } else if actions.contains(&StampAction::Rerun) {
+        println!("Rerun: {}", request_id);
+        let event = MessageCreatedUpdated {
+            event_time: event.event_time,
+            message: request.message,
+        };
+        handle_message(event, request.dm, cx.clone()).await;
+    } else if actions.contains(&StampAction::Kill) {
+        if let Some(job_id) = stats.jobs.kill(request_id) {
+            println!("Kill: {}", request_id);
+            stats.scheduler.cancel(&job_id);
+        }
+    }
+}
+
+/// Take the job of the request out of the queue, or kill it if it is running.
+/// Returns whether there was a job.
+fn cancel_job(stats: &Stats, request_id: &str) -> bool {
+    match stats.jobs.cancel(request_id) {
+        Some(job_id) => {
+            stats.scheduler.cancel(&job_id);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Reply to the message, editing the previous reply if there is one.
+async fn reply(stats: &Stats, api: &TraqApi, request: &Request, content: &str) {
+    if let Err(e) = stats.replies.send(api, request, content).await {
+        println!("failed to reply: {}", e);
     }
-    .boxed()
 }
 
-async fn python(
-    code: String,
+async fn run_code(
+    snippet: parse::Snippet,
     args: Vec<String>,
-    sandbox_dir: &str,
+    owner: &RunOwner,
+    cancel: &CancelToken,
     docker: &DockerManager,
-) -> Result<String, Box<dyn Error>> {
-    // prepare sandbox directory
-    let sandbox_dir = format!("{}/python-{}", sandbox_dir, uuid::Uuid::now_v7());
-    tokio::fs::create_dir_all(&sandbox_dir).await?;
-
-    // prepare sandbox input and output files
-    let input_file = format!("{}/python-code.py", &sandbox_dir);
+) -> Result<(String, Outcome), Box<dyn Error + Send + Sync>> {
+    // no fence tag means python
+    let tag = if snippet.lang.is_empty() {
+        "python"
+    } else {
+        snippet.lang.as_str()
+    };
 
-    // write code to input file
-    tokio::fs::write(&input_file, code).await?;
+    let Some(language) = docker.language(tag) else {
+        let available = docker
+            .languages()
+            .iter()
+            .map(|l| l.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let response = format!(
+            ":question: unknown language: `{}`\navailable: {}",
+            tag, available
+        );
+        return Ok((response, Outcome::Failure));
+    };
 
-    // run docker
     let result = docker
-        .python3(args, &sandbox_dir, "python-code.py", "output.txt")
+        .run_code(language, &snippet.code, args, snippet.stdin, owner, cancel)
         .await?;
 
     println!("result: {:?}", result);
 
-    // read output file
-    let mut output = tokio::fs::read_to_string(&format!("{}/output.txt", sandbox_dir)).await?;
+    let outcome = Outcome::of(&result);
+
+    let mut response = String::new();
+
+    if let Some(compile) = &result.compile {
+        response.push_str(&compile_line(compile));
+
+        if !compile.diagnostics.is_empty() {
+            response.push_str(&format!(
+                "\ncompiler output:\n```\n{}\n```",
+                section(&compile.diagnostics)
+            ));
+        }
+
+        if let Some(limit) = compile.limit_exceeded {
+            response.push_str(&format!("\n:warning: {}", limit));
+        }
 
-    // remove input and output files
-    tokio::fs::remove_file(&input_file).await?;
-    tokio::fs::remove_file(&format!("{}/output.txt", sandbox_dir)).await?;
+        if result.termination == Termination::CompileError {
+            return Ok((response, outcome));
+        }
+
+        response.push_str("\nrun: ");
+    }
+
+    response.push_str(&format!(
+        "{}\nstdout:\n```\n{}\n```",
+        status_line(&result),
+        section(&result.std_output)
+    ));
+
+    if !result.std_error.is_empty() {
+        response.push_str(&format!(
+            "\nstderr:\n```\n{}\n```",
+            section(&result.std_error)
+        ));
+    }
+
+    if let Some(limit) = result.limit_exceeded {
+        response.push_str(&format!("\n:warning: {}", limit));
+    }
+
+    Ok((response, outcome))
+}
+
+/// e.g. `compile: :x: compile error (exit code 1) | time: 850ms`
+fn compile_line(compile: &CompileResult) -> String {
+    let status = match (&compile.termination, compile.exit_code) {
+        (Termination::Exited, Some(0)) => ":white_check_mark: compiled".to_owned(),
+        (Termination::Exited, Some(code)) => format!(":x: compile error (exit code {})", code),
+        (Termination::TimedOut, _) => ":warning: compile time limit exceeded".to_owned(),
+        (termination, _) => format!(":x: compiler {}", termination),
+    };
+
+    format!("compile: {} | time: {}ms", status, compile.time.as_millis())
+}
+
+/// e.g. `removed 3 container(s).` followed by the failures
+fn remove_report(kind: &str, report: &RemoveReport) -> String {
+    let mut response = format!("removed {} {}(s).", report.removed, kind);
+
+    if !report.failed.is_empty() {
+        response.push_str(&format!(
+            "\n:warning: failed to remove {} {}(s):",
+            report.failed.len(),
+            kind
+        ));
+        for (id, e) in &report.failed {
+            response.push_str(&format!("\n- `{}`: {}", id, e));
+        }
+    }
+
+    response
+}
+
+/// Characters of one output section of a reply. traQ rejects messages over
+/// 10000 characters, and a reply has up to three sections.
+const MAX_SECTION_CHARS: usize = 3000;
+
+/// Output for a code block of the reply: without the last newline, and cut
+/// down to `MAX_SECTION_CHARS` with a marker.
+fn section(s: &str) -> Cow<'_, str> {
+    let s = trim_newline(s);
+
+    match s.char_indices().nth(MAX_SECTION_CHARS) {
+        Some((end, _)) => Cow::Owned(format!("{}\n... (truncated)", &s[..end])),
+        None => Cow::Borrowed(s),
+    }
+}
+
+fn trim_newline(s: &str) -> &str {
+    s.strip_suffix('\n').unwrap_or(s)
+}
+
+/// e.g. `:x: exited with code 1 | time: 120ms | memory: 9.8MiB`
+fn status_line(result: &RunResult) -> String {
+    let status = match (&result.termination, result.exit_code) {
+        (Termination::Exited, Some(0)) => ":white_check_mark: exited with code 0".to_owned(),
+        (Termination::Exited, Some(code)) => format!(":x: exited with code {}", code),
+        (Termination::TimedOut, _) => format!(":warning: {}", result.termination),
+        (termination, _) => format!(":x: {}", termination),
+    };
 
-    // remove sandbox directory
-    tokio::fs::remove_dir_all(&sandbox_dir).await?;
+    let mut line = format!("{} | time: {}ms", status, result.time.as_millis());
 
-    if output.ends_with('\n') {
-        output.pop();
+    if let Some(peak_memory) = result.peak_memory {
+        line.push_str(&format!(
+            " | memory: {:.1}MiB",
+            peak_memory as f64 / (1024.0 * 1024.0)
+        ));
     }
 
-    Ok(format!(
-        "time: {}ms\nstdout:\n```\n{}\n```",
-        result.time.as_millis(),
-        output
-    ))
+    line
 }
@@ -18,6 +18,7 @@ impl Parser {
             .insert(name.into(), regex::Regex::new(pattern.as_ref()).unwrap());
     }
 
+    #[allow(dead_code)]
     pub fn remove(&mut self, name: impl AsRef<str>) -> Option<regex::Regex> {
         self.regex.remove(name.as_ref())
     }
@@ -32,3 +33,104 @@ impl Parser {
         None
     }
 }
+
+/// A fenced code block: ```` ```{lang}\n{code}\n``` ````
+pub struct CodeBlock {
+    pub lang: String,
+    pub code: String,
+}
+
+/// Find the fenced code blocks in the source string, in order.
+pub fn code_blocks(src: &str) -> Vec<CodeBlock> {
+    let re = regex::Regex::new(r"(?s)```(?<lang>[^\n`]*)\n(?:(?<code>.*?)\n)?```").unwrap();
+
+    re.captures_iter(src)
+        .map(|caps| CodeBlock {
+            lang: caps["lang"].trim().to_string(),
+            code: caps
+                .name("code")
+                .map(|code| code.as_str().to_string())
+                .unwrap_or_default(),
+        })
+        .collect()
+}
+
+/// Code to run, taken from the first code block of a message.
+pub struct Snippet {
+    /// Fence tag of the code block. Empty if not given.
+    pub lang: String,
+    pub code: String,
+    /// Contents of a following ```` ```input ```` or ```` ```stdin ```` block.
+    pub stdin: Option<String>,
+}
+
+impl Snippet {
+    pub fn parse(src: &str) -> Option<Self> {
+        let mut blocks = code_blocks(src).into_iter();
+
+        let CodeBlock { lang, code } = blocks.next()?;
+
+        let stdin = blocks
+            .next()
+            .filter(|block| block.lang == "input" || block.lang == "stdin")
+            .map(|block| {
+                // the block strips the last newline. `input()` expects one.
+                let mut stdin = block.code;
+                if !stdin.is_empty() {
+                    stdin.push('\n');
+                }
+                stdin
+            });
+
+        Some(Self { lang, code, stdin })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_line_fence_is_not_a_snippet() {
+        assert!(Snippet::parse("```print(1)```").is_none());
+    }
+
+    #[test]
+    fn input_block_is_stdin() {
+        let snippet = Snippet::parse("```py\nprint(input())\n```\n```input\nhello\n```").unwrap();
+
+        assert_eq!(snippet.lang, "py");
+        assert_eq!(snippet.code, "print(input())");
+        assert_eq!(snippet.stdin.as_deref(), Some("hello\n"));
+    }
+
+    #[test]
+    fn stdin_block_is_stdin() {
+        let snippet = Snippet::parse("```\nx\n```\n```stdin\n1\n2\n```").unwrap();
+
+        assert_eq!(snippet.lang, "");
+        assert_eq!(snippet.stdin.as_deref(), Some("1\n2\n"));
+    }
+
+    #[test]
+    fn empty_input_block_has_no_newline() {
+        let snippet = Snippet::parse("```py\nx\n```\n```input\n```").unwrap();
+
+        assert_eq!(snippet.stdin.as_deref(), Some(""));
+    }
+
+    #[test]
+    fn other_block_is_not_stdin() {
+        let snippet = Snippet::parse("```py\nx\n```\n```text\nhello\n```").unwrap();
+
+        assert_eq!(snippet.stdin, None);
+    }
+
+    #[test]
+    fn no_second_block_is_no_stdin() {
+        let snippet = Snippet::parse("```py\nx\n```").unwrap();
+
+        assert_eq!(snippet.code, "x");
+        assert_eq!(snippet.stdin, None);
+    }
+}
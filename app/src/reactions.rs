@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+
+use traq_python_bot::traq_api::{TraqApi, TraqApiError};
+
+use crate::docker_manager::{RunResult, Termination};
+
+/// The stamps of the server, name -> id.
+#[derive(Default)]
+pub struct Stamps {
+    ids: HashMap<String, String>,
+}
+
+impl Stamps {
+    pub async fn fetch(api: &TraqApi) -> Result<Self, TraqApiError> {
+        let ids = api
+            .list_stamps()
+            .await?
+            .into_iter()
+            .map(|stamp| (stamp.name, stamp.id))
+            .collect();
+
+        Ok(Self { ids })
+    }
+
+    /// The id of the stamp named by the env var, or `default`. None if the name
+    /// is empty or not a stamp of the server.
+    pub fn id_from_env(&self, key: &str, default: &str) -> Option<String> {
+        let name = std::env::var(key).unwrap_or_else(|_| default.to_string());
+        if name.is_empty() {
+            return None;
+        }
+
+        let id = self.ids.get(&name).cloned();
+        if id.is_none() {
+            println!("stamp not found: {} ({})", name, key);
+        }
+        id
+    }
+}
+
+/// How a code request ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Success,
+    Failure,
+    Timeout,
+}
+
+impl Outcome {
+    pub fn of(result: &RunResult) -> Self {
+        if result.success() {
+            Outcome::Success
+        } else if result.termination == Termination::TimedOut {
+            Outcome::Timeout
+        } else {
+            Outcome::Failure
+        }
+    }
+}
+
+/// The stamps put on a code request: `running` while its job is queued or
+/// running, swapped for the stamp of the outcome once it ends.
+///
+/// Stamps are kept as ids. A stamp missing on the server is left out.
+pub struct Reactions {
+    running: Option<String>,
+    success: Option<String>,
+    failure: Option<String>,
+    timeout: Option<String>,
+}
+
+impl Reactions {
+    /// Stamp names from `STAMP_RUNNING`, `STAMP_SUCCESS`, `STAMP_FAILURE` and
+    /// `STAMP_TIMEOUT`. An empty name turns the stamp off.
+    pub fn from_env(stamps: &Stamps) -> Self {
+        Self {
+            running: stamps.id_from_env("STAMP_RUNNING", "loading"),
+            success: stamps.id_from_env("STAMP_SUCCESS", "white_check_mark"),
+            failure: stamps.id_from_env("STAMP_FAILURE", "x"),
+            timeout: stamps.id_from_env("STAMP_TIMEOUT", "warning"),
+        }
+    }
+
+    /// Mark the request as running, taking off the outcome of an earlier run.
+    pub async fn start(&self, api: &TraqApi, message_id: &str) {
+        for stamp_id in [&self.success, &self.failure, &self.timeout]
+            .into_iter()
+            .flatten()
+        {
+            remove(api, message_id, stamp_id).await;
+        }
+
+        if let Some(stamp_id) = &self.running {
+            add(api, message_id, stamp_id).await;
+        }
+    }
+
+    /// Swap the running stamp for the one of the outcome. Without an outcome,
+    /// e.g. when the job was cancelled, the running stamp is only taken off.
+    pub async fn finish(&self, api: &TraqApi, message_id: &str, outcome: Option<Outcome>) {
+        if let Some(stamp_id) = &self.running {
+            remove(api, message_id, stamp_id).await;
+        }
+
+        let stamp_id = match outcome {
+            Some(Outcome::Success) => &self.success,
+            Some(Outcome::Failure) => &self.failure,
+            Some(Outcome::Timeout) => &self.timeout,
+            None => &None,
+        };
+        if let Some(stamp_id) = stamp_id {
+            add(api, message_id, stamp_id).await;
+        }
+    }
+}
+
+/// What stamping a reply of the bot does to its request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StampAction {
+    /// Run the request again.
+    Rerun,
+    /// Kill the job of the request if it is still queued or running.
+    Kill,
+    /// Delete the reply.
+    Delete,
+}
+
+/// The stamps that act on the request when put on a reply.
+pub struct StampActions {
+    actions: HashMap<String, StampAction>,
+}
+
+impl StampActions {
+    /// Stamp names from `STAMP_ACTION_RERUN`, `STAMP_ACTION_KILL` and
+    /// `STAMP_ACTION_DELETE`. An empty name turns the action off.
+    pub fn from_env(stamps: &Stamps) -> Self {
+        let actions = [
+            ("STAMP_ACTION_RERUN", "repeat", StampAction::Rerun),
+            ("STAMP_ACTION_KILL", "no_entry", StampAction::Kill),
+            ("STAMP_ACTION_DELETE", "wastebasket", StampAction::Delete),
+        ]
+        .into_iter()
+        .filter_map(|(key, default, action)| Some((stamps.id_from_env(key, default)?, action)))
+        .collect();
+
+        Self { actions }
+    }
+
+    pub fn get(&self, stamp_id: &str) -> Option<StampAction> {
+        self.actions.get(stamp_id).copied()
+    }
+}
+
+async fn add(api: &TraqApi, message_id: &str, stamp_id: &str) {
+    if let Err(e) = api.add_stamp(message_id, stamp_id, 1).await {
+        println!("failed to stamp: {}", e);
+    }
+}
+
+async fn remove(api: &TraqApi, message_id: &str, stamp_id: &str) {
+    match api.remove_stamp(message_id, stamp_id).await {
+        // not stamped
+        Err(e) if e.status() == Some(reqwest::StatusCode::NOT_FOUND) => {}
+        Err(e) => println!("failed to remove stamp: {}", e),
+        Ok(()) => {}
+    }
+}
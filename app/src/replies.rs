@@ -0,0 +1,284 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+
+use traq_python_bot::{
+    event::{MessageBody, Stamp},
+    traq_api::{TraqApi, TraqApiError},
+};
+
+/// The reply of the bot to each request message, so it can be edited in place.
+///
+/// Only the latest `capacity` requests are remembered.
+pub struct Replies {
+    capacity: usize,
+    state: Mutex<RepliesState>,
+}
+
+/// A request message and where it was sent.
+#[derive(Clone)]
+pub struct Request {
+    pub message: MessageBody,
+    pub dm: bool,
+}
+
+#[derive(Default)]
+struct RepliesState {
+    /// request message id -> reply message id
+    replies: HashMap<String, String>,
+    /// reply message id -> the request it answers
+    requests: HashMap<String, Entry>,
+    /// request message ids, oldest first
+    order: VecDeque<String>,
+}
+
+struct Entry {
+    request: Request,
+    /// (user id, stamp id) -> count, as of the last stamp event on the reply
+    stamps: HashMap<(String, String), u32>,
+}
+
+impl Replies {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Mutex::new(RepliesState::default()),
+        }
+    }
+
+    pub fn get(&self, request_id: &str) -> Option<String> {
+        self.state.lock().unwrap().replies.get(request_id).cloned()
+    }
+
+    /// Forget the reply to the request. Returns the reply id.
+    pub fn remove(&self, request_id: &str) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        state.order.retain(|id| id != request_id);
+        let reply_id = state.replies.remove(request_id)?;
+        state.requests.remove(&reply_id);
+        Some(reply_id)
+    }
+
+    /// Record the reply to the request, or the new version of the request.
+    pub fn insert(&self, request: &Request, reply_id: &str) {
+        let mut state = self.state.lock().unwrap();
+        let request_id = &request.message.id;
+
+        let stamps = match state
+            .replies
+            .insert(request_id.to_string(), reply_id.to_string())
+        {
+            // the same reply edited for a new version of the request. keep the
+            // stamps seen on it.
+            Some(old) => state
+                .requests
+                .remove(&old)
+                .filter(|_| old == reply_id)
+                .map(|entry| entry.stamps),
+            None => {
+                state.order.push_back(request_id.to_string());
+                None
+            }
+        };
+        state.requests.insert(
+            reply_id.to_string(),
+            Entry {
+                request: request.clone(),
+                stamps: stamps.unwrap_or_default(),
+            },
+        );
+
+        while state.order.len() > self.capacity {
+            if let Some(oldest) = state.order.pop_front() {
+                if let Some(reply_id) = state.replies.remove(&oldest) {
+                    state.requests.remove(&reply_id);
+                }
+            }
+        }
+    }
+
+    /// Record the stamps now on the reply. Returns the request it answers and
+    /// the stamps added since the last call, or `None` if the reply is not known.
+    pub fn stamped<'a>(
+        &self,
+        reply_id: &str,
+        stamps: &'a [Stamp],
+    ) -> Option<(Request, Vec<&'a Stamp>)> {
+        let mut state = self.state.lock().unwrap();
+        let entry = state.requests.get_mut(reply_id)?;
+
+        let added = stamps
+            .iter()
+            .filter(|stamp| {
+                let key = (stamp.user_id.clone(), stamp.stamp_id.clone());
+                entry
+                    .stamps
+                    .get(&key)
+                    .is_none_or(|count| stamp.count > *count)
+            })
+            .collect();
+
+        entry.stamps = stamps
+            .iter()
+            .map(|stamp| ((stamp.user_id.clone(), stamp.stamp_id.clone()), stamp.count))
+            .collect();
+
+        Some((entry.request.clone(), added))
+    }
+
+    /// Reply to the request message: edit the previous reply, or post one.
+    pub async fn send(
+        &self,
+        api: &TraqApi,
+        request: &Request,
+        content: &str,
+    ) -> Result<(), TraqApiError> {
+        println!("Send:\n{}", content);
+
+        if let Some(reply_id) = self.get(&request.message.id) {
+            match api.edit_message(&reply_id, content, false).await {
+                // the request may have been edited since the reply was posted
+                Ok(()) => {
+                    self.insert(request, &reply_id);
+                    return Ok(());
+                }
+                // the reply was deleted. post a new one.
+                Err(e) if e.status() == Some(reqwest::StatusCode::NOT_FOUND) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let reply = api
+            .send_message(&request.message.channel_id, content, false)
+            .await?;
+        self.insert(request, &reply.id);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use traq_python_bot::event::User;
+
+    use super::*;
+
+    fn request(id: &str, text: &str) -> Request {
+        Request {
+            message: MessageBody {
+                id: id.to_string(),
+                user: User {
+                    id: "u1".to_string(),
+                    name: "alice".to_string(),
+                    display_name: "Alice".to_string(),
+                    icon_id: "i1".to_string(),
+                    bot: false,
+                },
+                channel_id: "c1".to_string(),
+                text: text.to_string(),
+                plain_text: text.to_string(),
+                embedded: Vec::new(),
+                created_at: "t".to_string(),
+                updated_at: "t".to_string(),
+            },
+            dm: false,
+        }
+    }
+
+    fn stamp(user_id: &str, stamp_id: &str, count: u32) -> Stamp {
+        Stamp {
+            stamp_id: stamp_id.to_string(),
+            user_id: user_id.to_string(),
+            stamp_name: String::new(),
+            count,
+            created_at: "t".to_string(),
+            updated_at: "t".to_string(),
+        }
+    }
+
+    /// (user id, stamp id) of the added stamps
+    fn added(replies: &Replies, reply_id: &str, stamps: &[Stamp]) -> Vec<(String, String)> {
+        let (_, added) = replies.stamped(reply_id, stamps).unwrap();
+        added
+            .into_iter()
+            .map(|stamp| (stamp.user_id.clone(), stamp.stamp_id.clone()))
+            .collect()
+    }
+
+    fn pair(user_id: &str, stamp_id: &str) -> (String, String) {
+        (user_id.to_string(), stamp_id.to_string())
+    }
+
+    #[test]
+    fn unknown_reply() {
+        let replies = Replies::new(8);
+
+        assert!(replies.stamped("r1", &[stamp("u1", "s1", 1)]).is_none());
+    }
+
+    #[test]
+    fn only_new_stamps_are_added() {
+        let replies = Replies::new(8);
+        replies.insert(&request("m1", "a"), "r1");
+
+        assert_eq!(
+            added(&replies, "r1", &[stamp("u1", "s1", 1)]),
+            [pair("u1", "s1")]
+        );
+        // the same stamps again
+        assert!(added(&replies, "r1", &[stamp("u1", "s1", 1)]).is_empty());
+        // another user, another stamp, and the same stamp once more
+        assert_eq!(
+            added(
+                &replies,
+                "r1",
+                &[
+                    stamp("u1", "s1", 2),
+                    stamp("u2", "s1", 1),
+                    stamp("u1", "s2", 1)
+                ]
+            ),
+            [pair("u1", "s1"), pair("u2", "s1"), pair("u1", "s2")]
+        );
+    }
+
+    #[test]
+    fn removed_stamp_is_added_again() {
+        let replies = Replies::new(8);
+        replies.insert(&request("m1", "a"), "r1");
+
+        added(&replies, "r1", &[stamp("u1", "s1", 1)]);
+        assert!(added(&replies, "r1", &[]).is_empty());
+        assert_eq!(
+            added(&replies, "r1", &[stamp("u1", "s1", 1)]),
+            [pair("u1", "s1")]
+        );
+    }
+
+    #[test]
+    fn edited_request_keeps_seen_stamps() {
+        let replies = Replies::new(8);
+        replies.insert(&request("m1", "old"), "r1");
+        added(&replies, "r1", &[stamp("u1", "s1", 1)]);
+
+        // the reply edited for the new version of the request
+        replies.insert(&request("m1", "new"), "r1");
+
+        let stamps = [stamp("u1", "s1", 1)];
+        let (request, added) = replies.stamped("r1", &stamps).unwrap();
+        assert_eq!(request.message.plain_text, "new");
+        assert!(added.is_empty());
+    }
+
+    #[test]
+    fn oldest_reply_is_forgotten() {
+        let replies = Replies::new(1);
+        replies.insert(&request("m1", "a"), "r1");
+        replies.insert(&request("m2", "b"), "r2");
+
+        assert!(replies.stamped("r1", &[]).is_none());
+        assert!(replies.stamped("r2", &[]).is_some());
+        assert_eq!(replies.get("m1"), None);
+    }
+}
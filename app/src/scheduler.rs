@@ -0,0 +1,325 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::{oneshot, Notify};
+
+/// FIFO queue of code runs with a global and a per-user concurrency cap.
+///
+/// A job waits in the queue until both caps allow it to start. A job of a user
+/// at the per-user cap is skipped, so jobs of other users behind it can start.
+pub struct Scheduler {
+    max_running: usize,
+    max_running_per_user: usize,
+    state: Mutex<SchedulerState>,
+    /// Notified when the last running job finishes.
+    idle: Notify,
+}
+
+#[derive(Default)]
+struct SchedulerState {
+    /// Set by `close`. No job is accepted afterwards.
+    closed: bool,
+    running: usize,
+    running_per_user: HashMap<String, usize>,
+    queue: VecDeque<QueuedJob>,
+}
+
+struct QueuedJob {
+    job_id: String,
+    user_id: String,
+    start: oneshot::Sender<Permit>,
+}
+
+/// A submitted job. Dropping it before it starts removes it from the queue.
+pub struct Ticket {
+    scheduler: Arc<Scheduler>,
+    job_id: String,
+    position: Option<usize>,
+    start: oneshot::Receiver<Permit>,
+}
+
+/// Slot of a running job. Dropping it lets the next job start.
+pub struct Permit {
+    scheduler: Arc<Scheduler>,
+    /// `None` once the slot is released.
+    user_id: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "job cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+impl Scheduler {
+    pub fn new(max_running: usize, max_running_per_user: usize) -> Arc<Self> {
+        Arc::new(Self {
+            max_running,
+            max_running_per_user,
+            state: Mutex::new(SchedulerState::default()),
+            idle: Notify::new(),
+        })
+    }
+
+    /// Queue a job of the user. It starts right away if there is a free slot.
+    /// The job is cancelled if the scheduler is closed.
+    pub fn submit(self: &Arc<Self>, user_id: impl Into<String>) -> Ticket {
+        let job_id = uuid::Uuid::now_v7().to_string();
+        let (start, start_rx) = oneshot::channel();
+
+        let position = {
+            let mut state = self.state.lock().unwrap();
+            // dropping `start` of a closed scheduler cancels the ticket
+            if !state.closed {
+                state.queue.push_back(QueuedJob {
+                    job_id: job_id.clone(),
+                    user_id: user_id.into(),
+                    start,
+                });
+                self.dispatch(&mut state);
+            }
+            state.queue.iter().position(|job| job.job_id == job_id)
+        };
+
+        Ticket {
+            scheduler: self.clone(),
+            job_id,
+            position,
+            start: start_rx,
+        }
+    }
+
+    /// Remove a queued job. Returns false if it is not in the queue.
+    pub fn cancel(&self, job_id: &str) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let len = state.queue.len();
+        state.queue.retain(|job| job.job_id != job_id);
+        len != state.queue.len()
+    }
+
+    /// Remove every queued job of the user. Returns how many were removed.
+    pub fn cancel_user(&self, user_id: &str) -> usize {
+        let mut state = self.state.lock().unwrap();
+        let len = state.queue.len();
+        state.queue.retain(|job| job.user_id != user_id);
+        len - state.queue.len()
+    }
+
+    /// Stop accepting jobs and cancel every queued job. Returns how many were cancelled.
+    /// Running jobs are left to finish, see `drain`.
+    pub fn close(&self) -> usize {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        let cancelled = state.queue.len();
+        state.queue.clear();
+        cancelled
+    }
+
+    /// Wait until no job is running.
+    pub async fn drain(&self) {
+        loop {
+            // registered before checking, so a release in between is not missed
+            let idle = self.idle.notified();
+            if self.state.lock().unwrap().running == 0 {
+                return;
+            }
+            idle.await;
+        }
+    }
+
+    /// Start every queued job the caps allow, in FIFO order.
+    fn dispatch(self: &Arc<Self>, state: &mut SchedulerState) {
+        let mut i = 0;
+        while i < state.queue.len() && state.running < self.max_running {
+            let user_id = &state.queue[i].user_id;
+            let running = state.running_per_user.get(user_id).copied().unwrap_or(0);
+
+            if running >= self.max_running_per_user {
+                i += 1;
+                continue;
+            }
+
+            let job = state.queue.remove(i).unwrap();
+            state.running += 1;
+            *state
+                .running_per_user
+                .entry(job.user_id.clone())
+                .or_default() += 1;
+
+            let permit = Permit {
+                scheduler: self.clone(),
+                user_id: Some(job.user_id),
+            };
+
+            // the ticket was dropped. release the slot without re-entering the lock.
+            if let Err(mut permit) = job.start.send(permit) {
+                if let Some(user_id) = permit.user_id.take() {
+                    self.release(state, &user_id);
+                }
+            }
+        }
+    }
+
+    fn release(&self, state: &mut SchedulerState, user_id: &str) {
+        state.running -= 1;
+        if let Some(running) = state.running_per_user.get_mut(user_id) {
+            *running -= 1;
+            if *running == 0 {
+                state.running_per_user.remove(user_id);
+            }
+        }
+        if state.running == 0 {
+            self.idle.notify_waiters();
+        }
+    }
+}
+
+impl Ticket {
+    pub fn job_id(&self) -> &str {
+        &self.job_id
+    }
+
+    /// Number of jobs ahead in the queue when submitted. `None` if it started right away.
+    pub fn position(&self) -> Option<usize> {
+        self.position
+    }
+
+    /// Wait until the job may start. Fails if the job was cancelled.
+    pub async fn wait(mut self) -> Result<Permit, Cancelled> {
+        (&mut self.start).await.map_err(|_| Cancelled)
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        self.scheduler.cancel(&self.job_id);
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(user_id) = self.user_id.take() {
+            let mut state = self.scheduler.state.lock().unwrap();
+            self.scheduler.release(&mut state, &user_id);
+            self.scheduler.dispatch(&mut state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queued(scheduler: &Scheduler) -> usize {
+        scheduler.state.lock().unwrap().queue.len()
+    }
+
+    fn running(scheduler: &Scheduler) -> usize {
+        scheduler.state.lock().unwrap().running
+    }
+
+    #[tokio::test]
+    async fn user_at_cap_is_skipped() {
+        let scheduler = Scheduler::new(2, 1);
+
+        let a1 = scheduler.submit("a");
+        let a2 = scheduler.submit("a");
+        let b1 = scheduler.submit("b");
+        assert_eq!(a1.position(), None);
+        assert_eq!(a2.position(), Some(0));
+        // starts ahead of a2, whose user is at the cap
+        assert_eq!(b1.position(), None);
+
+        let a1 = a1.wait().await.unwrap();
+        let _b1 = b1.wait().await.unwrap();
+        assert_eq!(queued(&scheduler), 1);
+
+        drop(a1);
+        let _a2 = a2.wait().await.unwrap();
+        assert_eq!(queued(&scheduler), 0);
+        assert_eq!(running(&scheduler), 2);
+    }
+
+    #[tokio::test]
+    async fn global_cap_keeps_fifo_order() {
+        let scheduler = Scheduler::new(1, 1);
+
+        let a = scheduler.submit("a").wait().await.unwrap();
+        let b = scheduler.submit("b");
+        let c = scheduler.submit("c");
+        assert_eq!(b.position(), Some(0));
+        assert_eq!(c.position(), Some(1));
+
+        drop(a);
+        let b = b.wait().await.unwrap();
+        assert_eq!(queued(&scheduler), 1);
+
+        drop(b);
+        let _c = c.wait().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn dropped_ticket_leaves_the_queue() {
+        let scheduler = Scheduler::new(1, 1);
+
+        let a = scheduler.submit("a").wait().await.unwrap();
+        let b = scheduler.submit("b");
+        assert_eq!(queued(&scheduler), 1);
+
+        drop(b);
+        assert_eq!(queued(&scheduler), 0);
+
+        drop(a);
+        assert_eq!(running(&scheduler), 0);
+    }
+
+    #[tokio::test]
+    async fn cancelled_ticket_fails() {
+        let scheduler = Scheduler::new(1, 1);
+
+        let _a = scheduler.submit("a").wait().await.unwrap();
+        let b = scheduler.submit("b");
+
+        assert!(scheduler.cancel(b.job_id()));
+        assert!(!scheduler.cancel(b.job_id()));
+        assert!(b.wait().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn cancel_user_removes_only_their_jobs() {
+        let scheduler = Scheduler::new(1, 1);
+
+        let _a = scheduler.submit("a").wait().await.unwrap();
+        let b1 = scheduler.submit("b");
+        let c = scheduler.submit("c");
+        let b2 = scheduler.submit("b");
+
+        assert_eq!(scheduler.cancel_user("b"), 2);
+        assert!(b1.wait().await.is_err());
+        assert!(b2.wait().await.is_err());
+        assert_eq!(queued(&scheduler), 1);
+        drop(c);
+    }
+
+    #[tokio::test]
+    async fn closed_scheduler_cancels_jobs() {
+        let scheduler = Scheduler::new(1, 1);
+
+        let a = scheduler.submit("a").wait().await.unwrap();
+        let b = scheduler.submit("b");
+
+        assert_eq!(scheduler.close(), 1);
+        assert!(b.wait().await.is_err());
+        assert!(scheduler.submit("c").wait().await.is_err());
+
+        drop(a);
+        scheduler.drain().await;
+    }
+}
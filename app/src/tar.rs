@@ -4,7 +4,7 @@ use tokio_tar::{Builder, Header};
 pub async fn create_tar_archive(
     dockerfile_dir: &std::path::Path,
     tar_file_path: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let tar_file = tokio::fs::File::create(tar_file_path).await?;
     let mut builder = Builder::new(tar_file);
 